@@ -0,0 +1,251 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{get_config_path, get_home_path},
+    copies::move_path,
+};
+
+#[cfg(test)]
+use crate::state::{DeployedKind, DotfilesState};
+
+#[derive(Default, Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub enum ConflictPolicy {
+    #[default]
+    #[serde(rename(serialize = "refuse", deserialize = "refuse"))]
+    Refuse,
+
+    #[serde(rename(serialize = "backup", deserialize = "backup"))]
+    Backup,
+}
+
+#[allow(clippy::to_string_trait_impl)]
+impl ToString for ConflictPolicy {
+    fn to_string(&self) -> String {
+        match self {
+            ConflictPolicy::Refuse => "refuse".to_string(),
+            ConflictPolicy::Backup => "backup".to_string(),
+        }
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub struct RestoreLog {
+    pub files: Vec<BackedUpFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub struct BackedUpFile {
+    pub original: PathBuf,
+    pub backup: PathBuf,
+}
+
+/// Backups made during one sync, created on disk only once the first file
+/// is moved aside. The id is the creation time in nanoseconds, bumped when
+/// another session already claimed it.
+pub struct BackupSession {
+    pub id: String,
+    pub dir: PathBuf,
+    log: RestoreLog,
+}
+
+pub fn get_backups_path() -> PathBuf {
+    get_config_path().join("backups")
+}
+
+impl BackupSession {
+    pub fn new() -> Self {
+        let id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos())
+            .unwrap_or_default()
+            .to_string();
+
+        BackupSession {
+            dir: get_backups_path().join(&id),
+            id,
+            log: RestoreLog::default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.log.files.is_empty()
+    }
+
+    /// Creates the session dir, moving on to the next id while another sync
+    /// started in the same instant holds it.
+    fn claim_dir(&mut self) -> io::Result<()> {
+        loop {
+            match fs::create_dir(&self.dir) {
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+                    let next = self.id.parse::<u128>().unwrap_or_default() + 1;
+
+                    self.id = next.to_string();
+                    self.dir = get_backups_path().join(&self.id);
+                }
+                created => return created,
+            }
+        }
+    }
+
+    /// Where `original` will be moved to, mirroring its path under `$HOME`.
+    pub fn backup_path(&self, original: &Path) -> PathBuf {
+        let home_dir = get_home_path();
+        let relative = original
+            .strip_prefix(&home_dir)
+            .unwrap_or(original.strip_prefix("/").unwrap_or(original));
+
+        self.dir.join(relative)
+    }
+
+    /// Moves `original` into the session dir and records it in the restore
+    /// log so `metl dotfiles restore-backup` can undo it.
+    pub fn backup(&mut self, original: &Path) -> io::Result<PathBuf> {
+        ensure_backups_ignored()?;
+
+        if self.is_empty() {
+            self.claim_dir()?;
+        }
+
+        let backup = self.backup_path(original);
+        if let Some(parent) = backup.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        move_path(original, &backup)?;
+
+        self.log.files.push(BackedUpFile {
+            original: original.to_path_buf(),
+            backup: backup.clone(),
+        });
+        write_restore_log(&self.dir, &self.log)?;
+
+        Ok(backup)
+    }
+}
+
+/// The metl config dir is the manifest repo, so backups must never be
+/// staged by `git add .`.
+fn ensure_backups_ignored() -> io::Result<()> {
+    let backups_path = get_backups_path();
+    fs::create_dir_all(&backups_path)?;
+
+    let gitignore = backups_path.join(".gitignore");
+    if !gitignore.exists() {
+        fs::write(gitignore, "*\n")?;
+    }
+
+    Ok(())
+}
+
+fn write_restore_log(dir: &Path, log: &RestoreLog) -> io::Result<()> {
+    let contents = toml::to_string_pretty(log).map_err(io::Error::other)?;
+
+    fs::write(dir.join("restore.toml"), contents)
+}
+
+pub fn read_restore_log(dir: &Path) -> io::Result<RestoreLog> {
+    let contents = fs::read_to_string(dir.join("restore.toml"))?;
+
+    toml::from_str::<RestoreLog>(&contents).map_err(io::Error::other)
+}
+
+/// Backup session ids, oldest first.
+pub fn list_backup_sessions() -> Vec<String> {
+    let Ok(entries) = fs::read_dir(get_backups_path()) else {
+        return vec![];
+    };
+
+    let mut sessions: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.path().join("restore.toml").exists())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+
+    sessions.sort_by_key(|id| id.parse::<u128>().unwrap_or_default());
+    sessions
+}
+
+/// Removes the link or copy metl put at `original` and moves the backup
+/// back. A real directory is only removed when empty so nothing the user
+/// added since is lost.
+pub fn restore_file(file: &BackedUpFile) -> io::Result<()> {
+    // NOTE: bail before touching the original if the backup is already gone
+    fs::symlink_metadata(&file.backup)?;
+
+    if let Ok(metadata) = fs::symlink_metadata(&file.original) {
+        match metadata.is_dir() {
+            true => fs::remove_dir(&file.original)?,
+            false => fs::remove_file(&file.original)?,
+        }
+    }
+
+    if let Some(parent) = file.original.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    move_path(&file.backup, &file.original)
+}
+
+#[test]
+fn test_backup_sessions_restore() {
+    let root = crate::config::test_config_dir().join("backup-sessions");
+    let original = root.join("home/.zshrc");
+    let other = root.join("home/.bashrc");
+
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(original.parent().unwrap()).unwrap();
+    fs::write(&original, "export EDITOR=nvim").unwrap();
+    fs::write(&other, "set -o vi").unwrap();
+
+    let mut first = BackupSession::new();
+    let mut second = BackupSession::new();
+
+    // NOTE: as if both syncs started in the same instant
+    second.id = first.id.clone();
+    second.dir = first.dir.clone();
+
+    let backup = first.backup(&original).unwrap();
+    second.backup(&other).unwrap();
+
+    assert_ne!(first.id, second.id);
+    assert_eq!(read_restore_log(&first.dir).unwrap().files.len(), 1);
+    assert_eq!(read_restore_log(&second.dir).unwrap().files.len(), 1);
+    assert!(list_backup_sessions().ends_with(&[first.id.clone(), second.id.clone()]));
+    assert!(!original.exists());
+
+    // NOTE: as if sync linked the package where the backups were
+    let source = root.join("dotfiles/zsh/.zshrc");
+    fs::create_dir_all(source.parent().unwrap()).unwrap();
+    fs::write(&source, "# deployed by metl").unwrap();
+    std::os::unix::fs::symlink(&source, &original).unwrap();
+    fs::write(&other, "# written by the user").unwrap();
+
+    let config = toml::to_string_pretty(&crate::config::Config::default()).unwrap();
+    fs::write(crate::config::get_config_file_path(), config).unwrap();
+
+    let mut state = DotfilesState::load();
+    state.record("zsh", &original, Some(&source), DeployedKind::Link);
+    state.save().unwrap();
+
+    crate::dotfiles::restore_backup(Some(first.id.clone()), false);
+
+    assert_eq!(fs::read_to_string(&original).unwrap(), "export EDITOR=nvim");
+    assert!(!backup.exists());
+    assert!(!first.dir.exists());
+    assert_eq!(DotfilesState::load().forget(&original), None);
+
+    // NOTE: a file metl never deployed is not overwritten
+    crate::dotfiles::restore_backup(Some(second.id.clone()), false);
+
+    assert_eq!(fs::read_to_string(&other).unwrap(), "# written by the user");
+    assert!(second.dir.exists());
+
+    let _ = fs::remove_dir_all(&root);
+    let _ = fs::remove_dir_all(&second.dir);
+}
@@ -0,0 +1,305 @@
+use std::{
+    path::Path,
+    process::{Command, Output},
+};
+
+use thiserror::Error;
+
+use crate::{
+    config::Config,
+    output::print_detail,
+    successes::{
+        dotfiles_cloned, dotfiles_updated, dry_run_dotfiles_clone, dry_run_dotfiles_update,
+    },
+};
+
+#[derive(Debug, Error)]
+pub enum RestoreError {
+    #[error("Failed to run git {command}: {error}")]
+    GitError {
+        command: String,
+        error: std::io::Error,
+    },
+
+    #[error("git {command} exited with code {code:?}\n{stderr}")]
+    GitFailed {
+        command: String,
+        code: Option<i32>,
+        stderr: String,
+    },
+
+    #[error("{path} exists but is not a git checkout")]
+    NotACheckout { path: String },
+
+    #[error("dotfiles checkout remote {found} does not match dotfiles_repo {expected}")]
+    RemoteMismatch { found: String, expected: String },
+
+    #[error(
+        "dotfiles checkout has local modifications, commit them or rerun with --force to discard them"
+    )]
+    LocalModifications,
+}
+
+/// Clones the dotfiles repo, or fast-forwards an existing checkout of it,
+/// then checks out `dotfiles_ref` and submodules when configured.
+pub fn checkout_dotfiles(
+    config: &Config,
+    force: bool,
+    dry_run: bool,
+    verbose: bool,
+) -> Result<(), RestoreError> {
+    let repo = &config.dotfiles_repo;
+    let path = config.dotfiles_path();
+    let path = path.as_path();
+
+    if !path.exists() {
+        if dry_run {
+            dry_run_dotfiles_clone(repo, path.to_path_buf());
+            return Ok(());
+        }
+
+        run_git(None, &["clone", repo, &path.to_string_lossy()], verbose)?;
+
+        if !config.dotfiles_ref.is_empty() {
+            checkout_ref(path, &config.dotfiles_ref, verbose)?;
+        }
+
+        update_submodules(config, path, verbose)?;
+        dotfiles_cloned(repo, path);
+
+        return Ok(());
+    }
+
+    if !path.join(".git").exists() {
+        return Err(RestoreError::NotACheckout {
+            path: path.to_string_lossy().to_string(),
+        });
+    }
+
+    let remote = git_stdout(path, &["remote", "get-url", "origin"], verbose)?;
+    if normalize_remote(&remote) != normalize_remote(repo) {
+        return Err(RestoreError::RemoteMismatch {
+            found: remote,
+            expected: repo.to_string(),
+        });
+    }
+
+    // NOTE: untracked files never block a fast-forward or checkout
+    let status = git_stdout(
+        path,
+        &["status", "--porcelain", "--untracked-files=no"],
+        verbose,
+    )?;
+    let modified = !status.is_empty();
+    if modified && !force {
+        return Err(RestoreError::LocalModifications);
+    }
+
+    if dry_run {
+        dry_run_dotfiles_update(path, &config.dotfiles_ref);
+        return Ok(());
+    }
+
+    if modified {
+        run_git(Some(path), &["reset", "--hard", "--quiet"], verbose)?;
+    }
+
+    run_git(Some(path), &["fetch", "--tags", "origin"], verbose)?;
+
+    match config.dotfiles_ref.as_str() {
+        "" => match is_detached(path, verbose) {
+            true => checkout_ref(path, &default_branch(path, verbose)?, verbose)?,
+            false => {
+                run_git(Some(path), &["merge", "--ff-only", "@{upstream}"], verbose)?;
+            }
+        },
+        git_ref => checkout_ref(path, git_ref, verbose)?,
+    }
+
+    update_submodules(config, path, verbose)?;
+    dotfiles_updated(path);
+
+    Ok(())
+}
+
+/// Branches are tracked and fast-forwarded, tags and commits are checked
+/// out detached so a fleet can be pinned to a known revision.
+fn checkout_ref(path: &Path, git_ref: &str, verbose: bool) -> Result<(), RestoreError> {
+    let remote_branch = format!("refs/remotes/origin/{git_ref}");
+    let is_branch = run_git(
+        Some(path),
+        &["rev-parse", "--verify", "--quiet", &remote_branch],
+        verbose,
+    )
+    .is_ok();
+
+    if is_branch {
+        run_git(Some(path), &["checkout", git_ref], verbose)?;
+        run_git(
+            Some(path),
+            &["merge", "--ff-only", &format!("origin/{git_ref}")],
+            verbose,
+        )?;
+    } else {
+        run_git(Some(path), &["checkout", "--detach", git_ref], verbose)?;
+    }
+
+    Ok(())
+}
+
+/// Whether HEAD is detached, as left behind by a `dotfiles_ref` that pinned
+/// a tag or commit and was unset since.
+fn is_detached(path: &Path, verbose: bool) -> bool {
+    run_git(Some(path), &["symbolic-ref", "--quiet", "HEAD"], verbose).is_err()
+}
+
+/// The branch `origin/HEAD` points at, asking the remote when the checkout
+/// does not know it yet.
+fn default_branch(path: &Path, verbose: bool) -> Result<String, RestoreError> {
+    let remote_head = ["symbolic-ref", "--short", "refs/remotes/origin/HEAD"];
+
+    let branch = match git_stdout(path, &remote_head, verbose) {
+        Ok(branch) => branch,
+        Err(_) => {
+            run_git(
+                Some(path),
+                &["remote", "set-head", "origin", "--auto"],
+                verbose,
+            )?;
+            git_stdout(path, &remote_head, verbose)?
+        }
+    };
+
+    Ok(branch
+        .strip_prefix("origin/")
+        .unwrap_or(&branch)
+        .to_string())
+}
+
+fn update_submodules(config: &Config, path: &Path, verbose: bool) -> Result<(), RestoreError> {
+    if config.dotfiles_submodules {
+        run_git(
+            Some(path),
+            &["submodule", "update", "--init", "--recursive"],
+            verbose,
+        )?;
+    }
+
+    Ok(())
+}
+
+pub fn run_git(dir: Option<&Path>, args: &[&str], verbose: bool) -> Result<Output, RestoreError> {
+    let mut command = Command::new("git");
+    if let Some(dir) = dir {
+        command.current_dir(dir);
+    }
+    command.args(args);
+
+    let output = command.output().map_err(|error| RestoreError::GitError {
+        command: args.join(" "),
+        error,
+    })?;
+
+    if verbose && let Ok(stdout) = String::from_utf8(output.stdout.clone()) {
+        print_detail(&stdout);
+    }
+
+    if verbose && let Ok(stderr) = String::from_utf8(output.stderr.clone()) {
+        print_detail(&stderr);
+    }
+
+    if !output.status.success() {
+        return Err(RestoreError::GitFailed {
+            command: args.join(" "),
+            code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
+    Ok(output)
+}
+
+fn git_stdout(dir: &Path, args: &[&str], verbose: bool) -> Result<String, RestoreError> {
+    let output = run_git(Some(dir), args, verbose)?;
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn normalize_remote(remote: &str) -> &str {
+    let remote = remote.trim().trim_end_matches('/');
+
+    remote.strip_suffix(".git").unwrap_or(remote)
+}
+
+#[test]
+fn test_checkout_dotfiles() {
+    let root = std::env::temp_dir().join(format!("metl-checkout-{}", std::process::id()));
+    let origin = root.join("origin");
+    let checkout = root.join("dotfiles");
+
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&origin).unwrap();
+
+    let git = |dir: &Path, args: &[&str]| {
+        let identity = ["-c", "user.name=metl", "-c", "user.email=metl@localhost"];
+        let args: Vec<&str> = identity.iter().chain(args).copied().collect();
+        run_git(Some(dir), &args, false).unwrap();
+    };
+    let commit = |message: &str| {
+        std::fs::write(origin.join(".zshrc"), message).unwrap();
+        git(&origin, &["add", "."]);
+        git(&origin, &["commit", "--quiet", "-m", message]);
+    };
+
+    git(&origin, &["init", "--quiet", "--initial-branch=main"]);
+    commit("first");
+    git(&origin, &["tag", "v1"]);
+
+    let mut config = Config {
+        dotfiles_repo: origin.to_string_lossy().to_string(),
+        dotfiles_dir: checkout.to_string_lossy().to_string(),
+        dotfiles_ref: "v1".to_string(),
+        ..Config::default()
+    };
+
+    checkout_dotfiles(&config, false, false, false).unwrap();
+    assert!(is_detached(&checkout, false));
+
+    // NOTE: unpinning follows the default branch again
+    commit("second");
+    config.dotfiles_ref = String::new();
+    checkout_dotfiles(&config, false, false, false).unwrap();
+    assert!(!is_detached(&checkout, false));
+    assert_eq!(
+        std::fs::read_to_string(checkout.join(".zshrc")).unwrap(),
+        "second"
+    );
+
+    std::fs::write(checkout.join(".zshrc"), "local").unwrap();
+    assert!(matches!(
+        checkout_dotfiles(&config, false, false, false),
+        Err(RestoreError::LocalModifications)
+    ));
+
+    // NOTE: forcing discards the modification so the update can go ahead
+    commit("third");
+    checkout_dotfiles(&config, true, false, false).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(checkout.join(".zshrc")).unwrap(),
+        "third"
+    );
+
+    std::fs::write(checkout.join(".untracked"), "scratch").unwrap();
+    checkout_dotfiles(&config, false, false, false).unwrap();
+
+    let other = Config {
+        dotfiles_repo: "https://example.com/dotfiles.git".to_string(),
+        ..config
+    };
+    assert!(matches!(
+        checkout_dotfiles(&other, false, false, false),
+        Err(RestoreError::RemoteMismatch { .. })
+    ));
+
+    let _ = std::fs::remove_dir_all(&root);
+}
@@ -1,4 +1,8 @@
-use std::{path::PathBuf, process::Command};
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    process::Command,
+};
 
 use thiserror::Error;
 
@@ -12,6 +16,9 @@ use crate::{
     },
 };
 
+const MANIFEST_REPO: &str = "metl manifest";
+const DOTFILES_REPO: &str = "dotfiles";
+
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug, Error)]
 pub enum CommitMetlManifestError {
@@ -26,72 +33,162 @@ pub enum CommitMetlManifestError {
 }
 
 pub fn commit_manifest(proxied_cmd: &str) {
-    let metl_config_path = get_config_path();
+    let config = load_config();
+    let commit_msg = format!(
+        "Updated with: {} {}",
+        config.package_manager.to_string(),
+        proxied_cmd
+    );
+
+    commit_metl_files(&commit_msg);
+}
+
+pub fn commit_metl_files(commit_msg: &str) {
+    commit_repo(
+        &get_config_path(),
+        MANIFEST_REPO,
+        "master",
+        &[OsStr::new(".")],
+        commit_msg,
+    );
+}
+
+/// Commits only `paths` in the dotfiles repo, leaving whatever else the user
+/// has in progress there alone.
+pub fn commit_dotfiles(dotfiles_path: &PathBuf, paths: &[PathBuf], commit_msg: &str) {
+    let pathspecs: Vec<&OsStr> = paths.iter().map(|path| path.as_os_str()).collect();
+
+    commit_repo(dotfiles_path, DOTFILES_REPO, "HEAD", &pathspecs, commit_msg);
+}
+
+/// Commits only `file` in the manifest repo, dated `date` when given, and
+/// leaves pushing to `push_metl_files` so a series of commits goes out at
+/// once. Returns whether the commit was made.
+pub fn commit_metl_file_at(file: &Path, commit_msg: &str, date: Option<&str>) -> bool {
+    let repo_path = get_config_path();
+
+    if let Err(git_add_error) = git_add_files(&repo_path, &[file.as_os_str()]) {
+        warn_git_add_error(MANIFEST_REPO, git_add_error);
+        return false;
+    }
+
+    let mut command = Command::new("git");
+    command.current_dir(&repo_path);
+    command.arg("commit").arg("-m").arg(commit_msg);
 
-    match git_add_files(&metl_config_path) {
-        Ok(_) => git_commit_metl_manifest(&metl_config_path, proxied_cmd),
+    if let Some(date) = date {
+        command.arg("--date").arg(date);
+        command.env("GIT_COMMITTER_DATE", date);
+    }
+
+    command.arg("--only").arg("--").arg(file);
 
-        Err(git_add_error) => match git_add_error {
-            CommitMetlManifestError::AddError { error } => {
-                warn_git_add_metl_manifest_failed(Some(error))
+    match command.output() {
+        Ok(output) => match output.status.code() {
+            Some(0) => {
+                git_metl_manifest_commit_success(MANIFEST_REPO, commit_msg);
+                true
+            }
+            Some(code) => {
+                warn_metl_manifest_commit_code(MANIFEST_REPO, code);
+                false
+            }
+            None => {
+                warn_metl_manifest_commit_failed(MANIFEST_REPO, None);
+                false
             }
-            CommitMetlManifestError::AddSigInt => warn_git_add_metl_manifest_failed(None),
-            CommitMetlManifestError::AddFailed { code } => warn_git_add_metl_manifest_code(code),
         },
+
+        Err(error) => {
+            warn_metl_manifest_commit_failed(MANIFEST_REPO, Some(error));
+            false
+        }
     }
 }
 
-fn git_commit_metl_manifest(metl_manifest_repo_path: &PathBuf, proxied_cmd: &str) {
-    let config = load_config();
-    let mut command = Command::new("git");
-    command.current_dir(metl_manifest_repo_path);
+pub fn push_metl_files() {
+    git_push_metl_manifest(&get_config_path(), MANIFEST_REPO, "master");
+}
 
-    let commit_msg = format!(
-        "Updated with: {} {}",
-        config.package_manager.to_string(),
-        proxied_cmd
-    );
+/// Stages `pathspecs` in `repo_path`, commits only them and pushes `branch`
+/// to origin. `repo` names the repository in messages.
+fn commit_repo(
+    repo_path: &PathBuf,
+    repo: &str,
+    branch: &str,
+    pathspecs: &[&OsStr],
+    commit_msg: &str,
+) {
+    match git_add_files(repo_path, pathspecs) {
+        Ok(_) => git_commit_metl_manifest(repo_path, repo, branch, pathspecs, commit_msg),
+        Err(git_add_error) => warn_git_add_error(repo, git_add_error),
+    }
+}
+
+fn warn_git_add_error(repo: &str, git_add_error: CommitMetlManifestError) {
+    match git_add_error {
+        CommitMetlManifestError::AddError { error } => {
+            warn_git_add_metl_manifest_failed(repo, Some(error))
+        }
+        CommitMetlManifestError::AddSigInt => warn_git_add_metl_manifest_failed(repo, None),
+        CommitMetlManifestError::AddFailed { code } => warn_git_add_metl_manifest_code(repo, code),
+    }
+}
+
+fn git_commit_metl_manifest(
+    repo_path: &PathBuf,
+    repo: &str,
+    branch: &str,
+    pathspecs: &[&OsStr],
+    commit_msg: &str,
+) {
+    let mut command = Command::new("git");
+    command.current_dir(repo_path);
     command.arg("commit").arg("-m").arg(commit_msg);
+    command.arg("--only").arg("--").args(pathspecs);
 
     match command.output() {
         Ok(output) => match output.status.code() {
             Some(code) => match code {
                 0 => {
-                    git_metl_manifest_commit_success(proxied_cmd);
-                    git_push_metl_manifest(metl_manifest_repo_path);
+                    git_metl_manifest_commit_success(repo, commit_msg);
+                    git_push_metl_manifest(repo_path, repo, branch);
                 }
-                code => warn_metl_manifest_commit_code(code),
+                code => warn_metl_manifest_commit_code(repo, code),
             },
 
-            None => warn_metl_manifest_commit_failed(None),
+            None => warn_metl_manifest_commit_failed(repo, None),
         },
 
-        Err(error) => warn_metl_manifest_commit_failed(Some(error)),
+        Err(error) => warn_metl_manifest_commit_failed(repo, Some(error)),
     }
 }
 
-pub fn git_push_metl_manifest(working_copy_path: &PathBuf) {
+pub fn git_push_metl_manifest(working_copy_path: &PathBuf, repo: &str, branch: &str) {
     let mut command = Command::new("git");
     command.current_dir(working_copy_path);
-    command.arg("push").arg("origin").arg("master");
+    command.arg("push").arg("origin").arg(branch);
 
     match command.output() {
         Ok(output) => match output.status.code() {
             Some(code) => match code {
-                0 => git_push_metl_manifest_success(),
-                code => warn_git_push_metl_manifest_failed(None, Some(code)),
+                0 => git_push_metl_manifest_success(repo),
+                code => warn_git_push_metl_manifest_failed(repo, None, Some(code)),
             },
-            None => warn_git_push_metl_manifest_failed(None, None),
+            None => warn_git_push_metl_manifest_failed(repo, None, None),
         },
 
-        Err(error) => warn_git_push_metl_manifest_failed(Some(error), None),
+        Err(error) => warn_git_push_metl_manifest_failed(repo, Some(error), None),
     }
 }
 
-fn git_add_files(working_copy_path: &PathBuf) -> Result<(), CommitMetlManifestError> {
+fn git_add_files(
+    working_copy_path: &PathBuf,
+    pathspecs: &[&OsStr],
+) -> Result<(), CommitMetlManifestError> {
     let mut command = Command::new("git");
     command.current_dir(working_copy_path);
-    command.arg("add").arg(".");
+    command.arg("add").arg("--").args(pathspecs);
 
     match command.output() {
         Ok(output) => match output.status.code() {
@@ -1,17 +1,172 @@
-use std::{fs::read_to_string, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsString,
+    fs::{self, read_to_string},
+    path::PathBuf,
+    sync::OnceLock,
+};
 
 use directories::UserDirs;
 use serde::{Deserialize, Serialize};
 
-use crate::{errors::missing_metl_config, manifest::PackageManager};
+use toml_edit::{DocumentMut, value};
+
+use crate::{
+    backups::ConflictPolicy,
+    copies::CopyPolicy,
+    errors::{config_parsing_error, missing_metl_config},
+    manifest::PackageManager,
+    privileges::Escalation,
+    validation::{Diagnostic, diagnose_syntax, parse_toml},
+    warnings::warn_config_migrated,
+};
+
+/// Bump this and add a step to `migrate_config` whenever a config field is
+/// renamed or changes type. New fields with a serde default need neither.
+pub const CONFIG_VERSION: i64 = 1;
 
 #[derive(Default, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    #[serde(default)]
+    pub version: i64,
+
     pub locked_versions: bool,
     pub package_manager: PackageManager,
     pub dotfiles_repo: String,
     pub dotfiles_symlink: bool,
     pub manifest_repo: String,
+
+    #[serde(default)]
+    pub escalation: Escalation,
+
+    #[serde(default)]
+    pub copy_policy: CopyPolicy,
+
+    /// Where the dotfiles repo is checked out, `~/` is expanded and relative
+    /// paths are taken from the home directory
+    #[serde(default = "default_dotfiles_dir")]
+    pub dotfiles_dir: String,
+
+    /// Branch, tag or commit to check out, empty tracks the default branch
+    #[serde(default)]
+    pub dotfiles_ref: String,
+
+    #[serde(default)]
+    pub dotfiles_submodules: bool,
+
+    /// What to do with existing files in the way of a dotfile, `backup`
+    /// moves them under the metl config dir before linking or overwriting
+    #[serde(default)]
+    pub conflict_policy: ConflictPolicy,
+
+    /// Values `*.tmpl` dotfiles can use as `{{ name }}`, on top of the built
+    /// in `hostname` and `user` and `env.NAME` for the environment
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+
+    /// age identity used to decrypt `*.age` dotfiles and encrypt new ones,
+    /// keep it outside of both the dotfiles and manifest repos
+    #[serde(default = "default_secrets_identity")]
+    pub secrets_identity: String,
+
+    /// Dotfile packages sync applies, empty applies every package in the
+    /// checkout
+    #[serde(default)]
+    pub dotfiles_packages: Vec<String>,
+
+    /// Picks an entry from `profiles`, `METL_PROFILE` overrides it
+    #[serde(default)]
+    pub profile: String,
+
+    /// Named dotfile package lists, used instead of `dotfiles_packages`
+    /// when selected by `profile`
+    #[serde(default)]
+    pub profiles: BTreeMap<String, DotfilesSelection>,
+
+    /// Dotfile package lists per hostname, used over the profile and the
+    /// global list
+    #[serde(default)]
+    pub hosts: BTreeMap<String, DotfilesSelection>,
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct DotfilesSelection {
+    #[serde(default)]
+    pub dotfiles_packages: Vec<String>,
+}
+
+fn default_dotfiles_dir() -> String {
+    "~/dotfiles".to_string()
+}
+
+fn default_secrets_identity() -> String {
+    "~/.config/age/keys.txt".to_string()
+}
+
+impl Config {
+    pub fn dotfiles_path(&self) -> PathBuf {
+        match self.dotfiles_dir.trim() {
+            "" => get_home_path().join("dotfiles"),
+            dir => expand_home(dir),
+        }
+    }
+
+    pub fn secrets_identity_path(&self) -> PathBuf {
+        match self.secrets_identity.trim() {
+            "" => expand_home(&default_secrets_identity()),
+            identity => expand_home(identity),
+        }
+    }
+
+    pub fn active_profile(&self) -> String {
+        env::var("METL_PROFILE")
+            .ok()
+            .filter(|profile| !profile.is_empty())
+            .unwrap_or_else(|| self.profile.clone())
+    }
+
+    /// The dotfile packages to apply on this machine, `None` applies all of
+    /// them.
+    pub fn selected_dotfiles(&self) -> Option<&[String]> {
+        self.dotfiles_selection(&get_hostname(), &self.active_profile())
+    }
+
+    /// The first non empty list of the host entry, the profile entry and the
+    /// global `dotfiles_packages`.
+    fn dotfiles_selection(&self, hostname: &str, profile: &str) -> Option<&[String]> {
+        let host = self.hosts.get(hostname);
+        let profile = self.profiles.get(profile);
+
+        [host, profile]
+            .into_iter()
+            .flatten()
+            .map(|selection| selection.dotfiles_packages.as_slice())
+            .chain([self.dotfiles_packages.as_slice()])
+            .find(|packages| !packages.is_empty())
+    }
+}
+
+/// Expands `~/`, relative paths are taken from the home directory.
+fn expand_home(path: &str) -> PathBuf {
+    let home_dir = get_home_path();
+
+    match path {
+        "~" => home_dir,
+        path => match path.strip_prefix("~/") {
+            Some(relative) => home_dir.join(relative),
+            None => home_dir.join(path),
+        },
+    }
+}
+
+pub fn get_hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| fs::read_to_string("/etc/hostname"))
+        .map(|hostname| hostname.trim().to_string())
+        .unwrap_or_default()
 }
 
 pub fn get_home_path() -> PathBuf {
@@ -22,30 +177,169 @@ pub fn get_home_path() -> PathBuf {
     user_dirs.home_dir().to_path_buf()
 }
 
+static CONFIG_DIR_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Overrides the metl config directory for the rest of the process, used by
+/// the global `--config-dir` flag and the `METL_HOME` environment variable.
+pub fn set_config_dir(config_dir: PathBuf) {
+    let _ = CONFIG_DIR_OVERRIDE.set(config_dir);
+}
+
+/// Points the config dir of the test binary at a scratch dir, shared by every
+/// test that reads or writes below it.
+#[cfg(test)]
+pub fn test_config_dir() -> PathBuf {
+    let config_dir = env::temp_dir().join(format!("metl-test-config-{}", std::process::id()));
+    set_config_dir(config_dir);
+
+    let config_path = get_config_path();
+    let _ = fs::create_dir_all(&config_path);
+    config_path
+}
+
 pub fn get_config_path() -> PathBuf {
-    let home_dir = get_home_path();
+    if let Some(config_dir) = CONFIG_DIR_OVERRIDE.get() {
+        return config_dir.clone();
+    }
+
+    resolve_config_path(
+        env::var_os("METL_HOME"),
+        env::var_os("XDG_CONFIG_HOME"),
+        get_home_path(),
+    )
+}
+
+/// The config dir of the user whose home is `home_dir`, unless
+/// `--config-dir` or `METL_HOME` chose one.
+pub fn get_user_config_path(home_dir: PathBuf) -> PathBuf {
+    if let Some(config_dir) = CONFIG_DIR_OVERRIDE.get() {
+        return config_dir.clone();
+    }
+
+    resolve_config_path(env::var_os("METL_HOME"), None, home_dir)
+}
+
+/// Whether `--config-dir` or `METL_HOME` chose the config dir.
+pub fn is_config_dir_chosen() -> bool {
+    CONFIG_DIR_OVERRIDE.get().is_some()
+        || env::var_os("METL_HOME").is_some_and(|dir| !dir.is_empty())
+}
+
+fn resolve_config_path(
+    metl_home: Option<OsString>,
+    xdg_config_home: Option<OsString>,
+    home_dir: PathBuf,
+) -> PathBuf {
+    if let Some(metl_home) = metl_home.filter(|dir| !dir.is_empty()) {
+        return PathBuf::from(metl_home);
+    }
 
-    home_dir.join(".config").join("metl")
+    // NOTE: the XDG spec says relative paths must be ignored
+    match xdg_config_home
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+    {
+        Some(xdg_config_home) => xdg_config_home.join("metl"),
+        None => home_dir.join(".config").join("metl"),
+    }
+}
+
+pub fn get_config_file_path() -> PathBuf {
+    get_config_path().join("config")
 }
 
 pub fn load_config() -> Config {
-    let metl_config_path = get_config_path().join("config");
+    let metl_config_path = get_config_file_path();
     let Ok(toml_str) = read_to_string(&metl_config_path) else {
         missing_metl_config(metl_config_path);
     };
 
-    let parse_error = format!("Error parsing metl config file at {metl_config_path:?}");
-    toml::from_str::<Config>(&toml_str).expect(&parse_error)
+    let (toml_str, migrated_from) = match migrate_config(&toml_str) {
+        Ok(Some((migrated, from))) => (migrated, Some(from)),
+        Ok(None) => (toml_str, None),
+        Err(diagnostic) => config_parsing_error(diagnostic, metl_config_path),
+    };
+
+    let config = match parse_toml::<Config>(&toml_str) {
+        Ok(config) => config,
+        Err(diagnostic) => config_parsing_error(diagnostic, metl_config_path),
+    };
+
+    // NOTE: only persist a migration once the result is known to be valid
+    if let Some(from) = migrated_from
+        && fs::write(&metl_config_path, &toml_str).is_ok()
+    {
+        warn_config_migrated(from, CONFIG_VERSION);
+    }
+
+    config
+}
+
+/// Upgrades an older config to `CONFIG_VERSION`, preserving comments and
+/// formatting. Returns the migrated source and the version it started at, or
+/// `None` when the config is already current.
+pub fn migrate_config(source: &str) -> Result<Option<(String, i64)>, Diagnostic> {
+    let mut document = match source.parse::<DocumentMut>() {
+        Ok(document) => document,
+        Err(error) => return Err(diagnose_syntax(source, &error)),
+    };
+
+    let from = document
+        .get("version")
+        .and_then(|version| version.as_integer())
+        .unwrap_or(0);
+
+    if from > CONFIG_VERSION {
+        return Err(Diagnostic {
+            message: format!(
+                "config version {from} is newer than this metl supports ({CONFIG_VERSION}), upgrade metl"
+            ),
+            line: None,
+            column: None,
+            source_line: None,
+            suggestion: None,
+        });
+    }
+
+    if from == CONFIG_VERSION {
+        return Ok(None);
+    }
+
+    // NOTE: v1 only introduced the `version` key itself, steps for later
+    // renames go here matched on the version they upgrade from
+
+    document["version"] = value(CONFIG_VERSION);
+
+    Ok(Some((document.to_string(), from)))
 }
 
 #[test]
 fn test_load_config() {
     let toml = r#"
+version = 1
 package_manager = "pacman"
 locked_versions = true
 dotfiles_repo = "repo_url"
 dotfiles_symlink = true
 manifest_repo = "repo_url"
+escalation = "doas"
+copy_policy = "overwrite"
+dotfiles_dir = "src/dotfiles"
+dotfiles_ref = "v1.2"
+dotfiles_submodules = true
+conflict_policy = "backup"
+secrets_identity = "~/.age/metl.txt"
+dotfiles_packages = ["zsh"]
+profile = "work"
+
+[variables]
+email = "me@example.com"
+
+[profiles.work]
+dotfiles_packages = ["zsh", "git"]
+
+[hosts.laptop]
+dotfiles_packages = ["zsh", "sway"]
 "#;
 
     let Ok(config) = toml::from_str::<Config>(toml) else {
@@ -55,11 +349,99 @@ manifest_repo = "repo_url"
     assert_eq!(
         config,
         Config {
+            version: 1,
             package_manager: PackageManager::Pacman,
             locked_versions: true,
             dotfiles_repo: "repo_url".into(),
             dotfiles_symlink: true,
-            manifest_repo: "manifest repo".into(),
+            manifest_repo: "repo_url".into(),
+            escalation: Escalation::Doas,
+            copy_policy: CopyPolicy::Overwrite,
+            dotfiles_dir: "src/dotfiles".into(),
+            dotfiles_ref: "v1.2".into(),
+            dotfiles_submodules: true,
+            conflict_policy: ConflictPolicy::Backup,
+            variables: BTreeMap::from([("email".into(), "me@example.com".into())]),
+            secrets_identity: "~/.age/metl.txt".into(),
+            dotfiles_packages: vec!["zsh".into()],
+            profile: "work".into(),
+            profiles: BTreeMap::from([(
+                "work".into(),
+                DotfilesSelection {
+                    dotfiles_packages: vec!["zsh".into(), "git".into()],
+                },
+            )]),
+            hosts: BTreeMap::from([(
+                "laptop".into(),
+                DotfilesSelection {
+                    dotfiles_packages: vec!["zsh".into(), "sway".into()],
+                },
+            )]),
         }
     );
+
+    let selected = |hostname, profile| config.dotfiles_selection(hostname, profile);
+    assert_eq!(
+        selected("laptop", "work"),
+        Some(&["zsh".into(), "sway".into()][..])
+    );
+    assert_eq!(
+        selected("desktop", "work"),
+        Some(&["zsh".into(), "git".into()][..])
+    );
+    assert_eq!(selected("desktop", ""), Some(&["zsh".into()][..]));
+    assert_eq!(Config::default().dotfiles_selection("laptop", "work"), None);
+}
+
+#[test]
+fn test_resolve_config_path() {
+    let home = PathBuf::from("/home/user");
+
+    assert_eq!(
+        resolve_config_path(None, None, home.clone()),
+        PathBuf::from("/home/user/.config/metl")
+    );
+
+    assert_eq!(
+        resolve_config_path(None, Some("/xdg".into()), home.clone()),
+        PathBuf::from("/xdg/metl")
+    );
+
+    assert_eq!(
+        resolve_config_path(None, Some("relative".into()), home.clone()),
+        PathBuf::from("/home/user/.config/metl")
+    );
+
+    assert_eq!(
+        resolve_config_path(Some("/profile".into()), Some("/xdg".into()), home),
+        PathBuf::from("/profile")
+    );
+}
+
+#[test]
+fn test_migrate_config_from_unversioned() {
+    let toml = r#"# keep me
+package_manager = "paru"
+locked_versions = false
+dotfiles_repo = "repo_url"
+dotfiles_symlink = false
+manifest_repo = "repo_url"
+"#;
+
+    let Ok(Some((migrated, from))) = migrate_config(toml) else {
+        panic!("Expected an unversioned config to be migrated");
+    };
+
+    assert_eq!(from, 0);
+    assert!(migrated.starts_with("# keep me"));
+
+    let Ok(config) = parse_toml::<Config>(&migrated) else {
+        panic!("Migrated config failed to parse");
+    };
+
+    assert_eq!(config.version, CONFIG_VERSION);
+    assert_eq!(config.escalation, Escalation::Sudo);
+    assert_eq!(config.copy_policy, CopyPolicy::SkipExisting);
+    assert_eq!(config.dotfiles_dir, "~/dotfiles");
+    assert!(matches!(migrate_config(&migrated), Ok(None)));
 }
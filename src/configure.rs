@@ -0,0 +1,324 @@
+use std::{collections::BTreeMap, env, fs, process::Command};
+
+use toml_edit::{DocumentMut, Item, TableLike, Value};
+
+use crate::{
+    commits::commit_metl_files,
+    config::{Config, DotfilesSelection, get_config_file_path, load_config},
+    errors::{
+        config_key_not_set, editor_failed, failed_reading_config, failed_writing_config,
+        invalid_config, invalid_config_value, unknown_config_key,
+    },
+    output::{Event, emit, is_json},
+    successes::{config_edited, config_value_set, config_value_unset},
+    validation::parse_toml,
+};
+
+pub fn config_get(key: &str) {
+    if schema_type(key).is_none() {
+        unknown_config_key(key, &config_keys());
+    }
+
+    let config = load_config();
+    let Ok(config_table) = toml::Table::try_from(&config) else {
+        invalid_config("could not serialize the loaded config");
+    };
+
+    let value = match lookup(&config_table, key) {
+        Some(value) => value,
+        None => config_key_not_set(key),
+    };
+
+    if is_json() {
+        return emit(Event::ConfigValue {
+            key: key.to_string(),
+            value: serde_json::to_value(value).unwrap_or_default(),
+        });
+    }
+
+    match value {
+        toml::Value::String(value) => println!("{value}"),
+        value => println!("{value}"),
+    }
+}
+
+pub fn config_list() {
+    let config = load_config();
+
+    if is_json() {
+        let Ok(toml::Value::Table(config_table)) = toml::Value::try_from(&config) else {
+            invalid_config("could not serialize the loaded config");
+        };
+
+        return config_table.into_iter().for_each(|(key, value)| {
+            emit(Event::ConfigValue {
+                key,
+                value: serde_json::to_value(value).unwrap_or_default(),
+            })
+        });
+    }
+
+    let Ok(config_output) = toml::to_string_pretty(&config) else {
+        invalid_config("could not serialize the loaded config");
+    };
+
+    print!("{config_output}");
+}
+
+pub fn config_set(key: &str, value: &str, commit: bool) {
+    let Some(expected) = schema_type(key) else {
+        unknown_config_key(key, &config_keys());
+    };
+
+    let parsed = match parse_value(&expected, value) {
+        Ok(parsed) => parsed,
+        Err(reason) => invalid_config_value(key, value, &reason),
+    };
+
+    let mut document = read_config_document();
+
+    let mut table = document.as_table_mut();
+    let mut segments = key.split('.').peekable();
+    while let Some(segment) = segments.next() {
+        if segments.peek().is_none() {
+            table[segment] = Item::Value(parsed.clone());
+            break;
+        }
+
+        if !table.contains_key(segment) {
+            table[segment] = toml_edit::table();
+        }
+
+        if let Some(inline) = table[segment].as_inline_table() {
+            table[segment] = Item::Table(inline.clone().into_table());
+        }
+
+        let Some(child) = table[segment].as_table_mut() else {
+            invalid_config_value(key, value, "parent key is not a table");
+        };
+        table = child;
+    }
+
+    write_config_document(&document);
+    config_value_set(key, value);
+
+    if commit {
+        commit_metl_files(&format!("Config: set {key} = {value}"));
+    }
+}
+
+pub fn config_unset(key: &str, commit: bool) {
+    if schema_type(key).is_none() {
+        unknown_config_key(key, &config_keys());
+    }
+
+    let mut document = read_config_document();
+
+    let (parents, leaf) = match key.rsplit_once('.') {
+        Some((parents, leaf)) => (Some(parents), leaf),
+        None => (None, key),
+    };
+
+    let mut table: &mut dyn TableLike = document.as_table_mut();
+    for segment in parents.into_iter().flat_map(|parents| parents.split('.')) {
+        let Some(child) = table.get_mut(segment).and_then(Item::as_table_like_mut) else {
+            config_key_not_set(key);
+        };
+        table = child;
+    }
+
+    if table.remove(leaf).is_none() {
+        config_key_not_set(key);
+    }
+
+    write_config_document(&document);
+    config_value_unset(key);
+
+    if commit {
+        commit_metl_files(&format!("Config: unset {key}"));
+    }
+}
+
+pub fn config_edit(commit: bool) {
+    let config_path = get_config_file_path();
+    let original = fs::read_to_string(&config_path).unwrap_or_default();
+
+    let editor = preferred_editor();
+
+    let status = match Command::new(&editor).arg(&config_path).status() {
+        Ok(status) => status,
+        Err(error) => editor_failed(&editor, Some(error)),
+    };
+
+    if !status.success() {
+        editor_failed(&editor, None);
+    }
+
+    let edited = fs::read_to_string(&config_path).unwrap_or_default();
+    if let Err(diagnostic) = parse_toml::<Config>(&edited) {
+        let _ = fs::write(&config_path, original);
+        invalid_config(&diagnostic.to_string());
+    }
+
+    if edited == original {
+        return;
+    }
+
+    config_edited(config_path);
+
+    if commit {
+        commit_metl_files("Config: edited config");
+    }
+}
+
+pub fn preferred_editor() -> String {
+    ["VISUAL", "EDITOR"]
+        .into_iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|editor| !editor.is_empty())
+        .unwrap_or_else(|| "vi".to_string())
+}
+
+fn read_config_document() -> DocumentMut {
+    let config_path = get_config_file_path();
+
+    let contents = match fs::read_to_string(&config_path) {
+        Ok(contents) => contents,
+        Err(error) => failed_reading_config(error, config_path),
+    };
+
+    match contents.parse::<DocumentMut>() {
+        Ok(document) => document,
+        Err(error) => invalid_config(error.message()),
+    }
+}
+
+/// Validates the edited document against `Config` before it replaces the
+/// file on disk, so a bad value never leaves metl unable to start.
+fn write_config_document(document: &DocumentMut) {
+    let contents = document.to_string();
+
+    if let Err(diagnostic) = parse_toml::<Config>(&contents) {
+        invalid_config(&diagnostic.to_string());
+    }
+
+    let config_path = get_config_file_path();
+    if let Err(error) = fs::write(&config_path, contents) {
+        failed_writing_config(error, config_path);
+    }
+}
+
+fn lookup<'a>(table: &'a toml::Table, key: &str) -> Option<&'a toml::Value> {
+    let mut segments = key.split('.');
+    let mut value = table.get(segments.next()?)?;
+
+    for segment in segments {
+        value = value.as_table()?.get(segment)?;
+    }
+
+    Some(value)
+}
+
+/// Stands in for the entries of map fields like `variables` and `profiles`,
+/// where any name is a valid key.
+const MAP_ENTRY: &str = "<name>";
+
+/// The default `Config` doubles as the schema: every known key and the type
+/// of value it takes, with one `MAP_ENTRY` in each map field.
+fn schema() -> toml::Table {
+    let selection = || BTreeMap::from([(MAP_ENTRY.to_string(), DotfilesSelection::default())]);
+
+    let config = Config {
+        variables: BTreeMap::from([(MAP_ENTRY.to_string(), String::new())]),
+        profiles: selection(),
+        hosts: selection(),
+        ..Config::default()
+    };
+
+    toml::Table::try_from(config).unwrap_or_default()
+}
+
+/// The type of value `key` takes, any name matching a map entry.
+fn schema_type(key: &str) -> Option<toml::Value> {
+    let schema = schema();
+    let mut table = &schema;
+    let mut segments = key.split('.').peekable();
+
+    while let Some(segment) = segments.next() {
+        let value = table.get(segment).or_else(|| table.get(MAP_ENTRY))?;
+
+        match (segments.peek(), value) {
+            (None, value) => return Some(value.clone()),
+            (Some(_), toml::Value::Table(child)) => table = child,
+            (Some(_), _) => return None,
+        }
+    }
+
+    None
+}
+
+fn config_keys() -> Vec<String> {
+    let mut keys = vec![];
+    collect_keys(&schema(), "", &mut keys);
+    keys
+}
+
+fn collect_keys(table: &toml::Table, prefix: &str, keys: &mut Vec<String>) {
+    table.iter().for_each(|(key, value)| {
+        let key = format!("{prefix}{key}");
+
+        match value {
+            toml::Value::Table(child) if !child.is_empty() => {
+                collect_keys(child, &format!("{key}."), keys)
+            }
+            _ => keys.push(key),
+        }
+    });
+}
+
+fn parse_value(expected: &toml::Value, raw: &str) -> Result<Value, String> {
+    match expected {
+        toml::Value::String(_) => Ok(Value::from(raw)),
+
+        toml::Value::Boolean(_) => raw
+            .parse::<bool>()
+            .map(Value::from)
+            .map_err(|_| "expected true or false".to_string()),
+
+        toml::Value::Integer(_) => raw
+            .parse::<i64>()
+            .map(Value::from)
+            .map_err(|_| "expected an integer".to_string()),
+
+        _ => raw.parse::<Value>().map_err(|error| error.to_string()),
+    }
+}
+
+#[test]
+fn test_parse_value_follows_schema() {
+    let Some(expected) = schema_type("dotfiles_symlink") else {
+        panic!("dotfiles_symlink missing from schema");
+    };
+    assert!(parse_value(&expected, "true").is_ok());
+    assert!(parse_value(&expected, "yes").is_err());
+
+    let Some(expected) = schema_type("dotfiles_repo") else {
+        panic!("dotfiles_repo missing from schema");
+    };
+    assert_eq!(
+        parse_value(&expected, "true").map(|value| value.as_str().map(String::from)),
+        Ok(Some("true".to_string()))
+    );
+
+    assert!(schema_type("package_manger").is_none());
+
+    let Some(expected) = schema_type("profiles.work.dotfiles_packages") else {
+        panic!("profile entries missing from schema");
+    };
+    assert!(parse_value(&expected, r#"["zsh", "git"]"#).is_ok_and(|value| value.is_array()));
+
+    assert!(schema_type("hosts.laptop").is_some());
+    assert!(schema_type("variables.email").is_some_and(|value| value.is_str()));
+    assert!(schema_type("hosts.laptop.dotfiles_packges").is_none());
+    assert!(config_keys().contains(&"hosts.<name>.dotfiles_packages".to_string()));
+}
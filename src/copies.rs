@@ -0,0 +1,467 @@
+use std::{
+    fs::{self, File, FileTimes, Permissions},
+    io::{self, Write},
+    os::unix::{
+        self,
+        fs::{OpenOptionsExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    ignores::IgnoreRules,
+    secrets::{decrypt, is_secret},
+    templates::{TemplateContext, is_template},
+};
+
+#[derive(Default, Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub enum CopyPolicy {
+    #[default]
+    #[serde(rename(serialize = "skip-existing", deserialize = "skip-existing"))]
+    SkipExisting,
+
+    #[serde(rename(serialize = "overwrite", deserialize = "overwrite"))]
+    Overwrite,
+
+    #[serde(rename(serialize = "overwrite-if-newer", deserialize = "overwrite-if-newer"))]
+    OverwriteIfNewer,
+
+    #[serde(rename(serialize = "prompt", deserialize = "prompt"))]
+    Prompt,
+}
+
+#[allow(clippy::to_string_trait_impl)]
+impl ToString for CopyPolicy {
+    fn to_string(&self) -> String {
+        match self {
+            CopyPolicy::SkipExisting => "skip-existing".to_string(),
+            CopyPolicy::Overwrite => "overwrite".to_string(),
+            CopyPolicy::OverwriteIfNewer => "overwrite-if-newer".to_string(),
+            CopyPolicy::Prompt => "prompt".to_string(),
+        }
+    }
+}
+
+/// A single file level step needed to copy a dotfile package into the
+/// target dir.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CopyAction {
+    CreateDir {
+        source: PathBuf,
+        target: PathBuf,
+    },
+    Create {
+        source: PathBuf,
+        target: PathBuf,
+    },
+    Overwrite {
+        source: PathBuf,
+        target: PathBuf,
+    },
+    Prompt {
+        source: PathBuf,
+        target: PathBuf,
+    },
+    Skip {
+        source: PathBuf,
+        target: PathBuf,
+        reason: &'static str,
+    },
+}
+
+/// What is needed to turn `*.tmpl` templates and `*.age` secrets into the
+/// files deployed in their place.
+pub struct RenderContext {
+    pub templates: TemplateContext,
+    pub identity: PathBuf,
+}
+
+impl RenderContext {
+    pub fn new(config: &Config) -> Self {
+        RenderContext {
+            templates: TemplateContext::new(config),
+            identity: config.secrets_identity_path(),
+        }
+    }
+}
+
+pub fn is_rendered(path: &Path) -> bool {
+    is_template(path) || is_secret(path)
+}
+
+/// Where a rendered file ends up, `.zshrc.tmpl` becomes `.zshrc`.
+pub fn rendered_target(path: &Path) -> PathBuf {
+    match is_rendered(path) {
+        true => path.with_extension(""),
+        false => path.to_path_buf(),
+    }
+}
+
+/// Plans copying the contents of `source_dir` into `target_dir`, deciding
+/// what happens to files that already exist according to `policy`. With
+/// `render`, templates and secrets are planned under their rendered name
+/// and compared by their rendered contents. With `ignores`, whatever they
+/// match is left out.
+pub fn plan_copy(
+    source_dir: &Path,
+    target_dir: &Path,
+    policy: &CopyPolicy,
+    render: Option<&RenderContext>,
+    ignores: Option<&IgnoreRules>,
+) -> io::Result<Vec<CopyAction>> {
+    let mut actions = vec![];
+    plan_dir(
+        source_dir,
+        target_dir,
+        policy,
+        render,
+        ignores,
+        &mut actions,
+    )?;
+
+    Ok(actions)
+}
+
+fn plan_dir(
+    source_dir: &Path,
+    target_dir: &Path,
+    policy: &CopyPolicy,
+    render: Option<&RenderContext>,
+    ignores: Option<&IgnoreRules>,
+    actions: &mut Vec<CopyAction>,
+) -> io::Result<()> {
+    let mut children: Vec<_> = fs::read_dir(source_dir)?
+        .flatten()
+        .filter(|entry| entry.file_name() != ".git")
+        .filter(|entry| {
+            let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+            ignores.is_none_or(|ignores| !ignores.is_ignored(&entry.path(), is_dir))
+        })
+        .map(|entry| entry.file_name())
+        .collect();
+    children.sort();
+
+    for name in children {
+        let source = source_dir.join(&name);
+        let target = target_dir.join(&name);
+        let source_metadata = fs::symlink_metadata(&source)?;
+
+        if source_metadata.is_dir() {
+            if !target.is_dir() {
+                actions.push(CopyAction::CreateDir {
+                    source: source.clone(),
+                    target: target.clone(),
+                });
+            }
+
+            plan_dir(&source, &target, policy, render, ignores, actions)?;
+            continue;
+        }
+
+        // NOTE: render up front so a broken template or secret fails the plan
+        let (target, rendered) = match render {
+            Some(render) if is_rendered(&source) && source_metadata.is_file() => (
+                rendered_target(&target),
+                Some(render_file(&source, render)?),
+            ),
+            _ => (target, None),
+        };
+
+        let Ok(target_metadata) = fs::symlink_metadata(&target) else {
+            actions.push(CopyAction::Create { source, target });
+            continue;
+        };
+
+        if target_metadata.is_dir() {
+            actions.push(CopyAction::Skip {
+                source,
+                target,
+                reason: "a directory exists at the target",
+            });
+            continue;
+        }
+
+        let unchanged = match &rendered {
+            Some(rendered) => fs::read(&target).is_ok_and(|current| &current == rendered),
+            None => is_unchanged(&source, &target, None),
+        };
+
+        if unchanged {
+            actions.push(CopyAction::Skip {
+                source,
+                target,
+                reason: "unchanged",
+            });
+            continue;
+        }
+
+        let action = match policy {
+            CopyPolicy::SkipExisting => CopyAction::Skip {
+                source,
+                target,
+                reason: "already exists",
+            },
+
+            CopyPolicy::Overwrite => CopyAction::Overwrite { source, target },
+
+            CopyPolicy::OverwriteIfNewer => {
+                match (source_metadata.modified(), target_metadata.modified()) {
+                    (Ok(source_time), Ok(target_time)) if source_time > target_time => {
+                        CopyAction::Overwrite { source, target }
+                    }
+                    _ => CopyAction::Skip {
+                        source,
+                        target,
+                        reason: "target is newer",
+                    },
+                }
+            }
+
+            CopyPolicy::Prompt => CopyAction::Prompt { source, target },
+        };
+
+        actions.push(action);
+    }
+
+    Ok(())
+}
+
+pub fn is_unchanged(source: &Path, target: &Path, render: Option<&RenderContext>) -> bool {
+    if let Some(render) = render
+        && is_rendered(source)
+    {
+        return matches!(
+            (render_file(source, render), fs::read(target)),
+            (Ok(rendered), Ok(current)) if rendered == current
+        );
+    }
+
+    match (fs::symlink_metadata(source), fs::symlink_metadata(target)) {
+        (Ok(source_metadata), Ok(target_metadata))
+            if source_metadata.is_symlink() && target_metadata.is_symlink() =>
+        {
+            fs::read_link(source).ok() == fs::read_link(target).ok()
+        }
+
+        (Ok(source_metadata), Ok(target_metadata))
+            if source_metadata.len() == target_metadata.len()
+                && !source_metadata.is_symlink()
+                && !target_metadata.is_symlink() =>
+        {
+            matches!((fs::read(source), fs::read(target)), (Ok(a), Ok(b)) if a == b)
+        }
+
+        _ => false,
+    }
+}
+
+/// Copies a single file or link, preserving permissions and timestamps.
+pub fn copy_file(source: &Path, target: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(source)?;
+
+    if fs::symlink_metadata(target).is_ok() {
+        fs::remove_file(target)?;
+    }
+
+    if metadata.is_symlink() {
+        return unix::fs::symlink(fs::read_link(source)?, target);
+    }
+
+    // NOTE: fs::copy already carries the permission bits over
+    fs::copy(source, target)?;
+
+    let times = FileTimes::new()
+        .set_accessed(metadata.accessed()?)
+        .set_modified(metadata.modified()?);
+
+    // NOTE: read only, the copy may be read-only and futimens only needs
+    // ownership
+    File::open(target)?.set_times(times)
+}
+
+/// The contents a template or secret deploys as.
+pub fn render_file(source: &Path, render: &RenderContext) -> io::Result<Vec<u8>> {
+    if is_secret(source) {
+        return decrypt(source, &render.identity);
+    }
+
+    let contents = fs::read_to_string(source)?;
+
+    render
+        .templates
+        .render(&contents)
+        .map(String::into_bytes)
+        .map_err(|error| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {error}", source.to_string_lossy()),
+            )
+        })
+}
+
+/// Writes `source` to `target`, rendering it first when it is a template or
+/// secret. Decrypted secrets are only readable by the owner.
+pub fn install_file(
+    source: &Path,
+    target: &Path,
+    render: Option<&RenderContext>,
+) -> io::Result<()> {
+    let Some(render) = render.filter(|_| is_rendered(source)) else {
+        return copy_file(source, target);
+    };
+
+    let rendered = render_file(source, render)?;
+
+    if fs::symlink_metadata(target).is_ok() {
+        fs::remove_file(target)?;
+    }
+
+    let permissions = match is_secret(source) {
+        true => Permissions::from_mode(0o600),
+        false => fs::metadata(source)?.permissions(),
+    };
+
+    write_restricted(target, &rendered)?;
+    fs::set_permissions(target, permissions)
+}
+
+/// Creates `target` with `contents`, readable only by the owner.
+pub fn write_restricted(target: &Path, contents: &[u8]) -> io::Result<()> {
+    // NOTE: create the file restricted so a secret is never briefly readable
+    File::options()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(target)?
+        .write_all(contents)
+}
+
+/// Creates `target` with the permissions of `source`. Its timestamps are left
+/// alone, copying the contents in would change them again anyway.
+pub fn create_dir(source: &Path, target: &Path) -> io::Result<()> {
+    fs::create_dir_all(target)?;
+    fs::set_permissions(target, fs::metadata(source)?.permissions())
+}
+
+/// Copies a file, link or whole directory to `target`, overwriting what is
+/// there.
+pub fn copy_tree(source: &Path, target: &Path) -> io::Result<()> {
+    if !fs::symlink_metadata(source)?.is_dir() {
+        return copy_file(source, target);
+    }
+
+    create_dir(source, target)?;
+
+    for action in plan_copy(source, target, &CopyPolicy::Overwrite, None, None)? {
+        match action {
+            CopyAction::CreateDir { source, target } => create_dir(&source, &target)?,
+            CopyAction::Create { source, target } | CopyAction::Overwrite { source, target } => {
+                copy_file(&source, &target)?
+            }
+            CopyAction::Prompt { .. } | CopyAction::Skip { .. } => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Moves a file, link or directory, copying it over and removing the source
+/// when `source` and `target` are on different filesystems.
+pub fn move_path(source: &Path, target: &Path) -> io::Result<()> {
+    match fs::rename(source, target) {
+        Err(error) if error.kind() == io::ErrorKind::CrossesDevices => {
+            copy_tree(source, target)?;
+
+            match fs::symlink_metadata(source)?.is_dir() {
+                true => fs::remove_dir_all(source),
+                false => fs::remove_file(source),
+            }
+        }
+        moved => moved,
+    }
+}
+
+#[test]
+fn test_plan_copy_respects_policy() {
+    let root = std::env::temp_dir().join(format!("metl-copies-{}", std::process::id()));
+    let source = root.join("dotfiles/git");
+    let home = root.join("home");
+
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(source.join(".config/git")).unwrap();
+    fs::create_dir_all(&home).unwrap();
+    fs::write(source.join(".gitconfig"), "[user]").unwrap();
+    fs::write(source.join(".config/git/ignore"), "target").unwrap();
+    fs::write(home.join(".gitconfig"), "[core]").unwrap();
+
+    let actions = plan_copy(&source, &home, &CopyPolicy::SkipExisting, None, None).unwrap();
+    assert_eq!(
+        actions,
+        vec![
+            CopyAction::CreateDir {
+                source: source.join(".config"),
+                target: home.join(".config"),
+            },
+            CopyAction::CreateDir {
+                source: source.join(".config/git"),
+                target: home.join(".config/git"),
+            },
+            CopyAction::Create {
+                source: source.join(".config/git/ignore"),
+                target: home.join(".config/git/ignore"),
+            },
+            CopyAction::Skip {
+                source: source.join(".gitconfig"),
+                target: home.join(".gitconfig"),
+                reason: "already exists",
+            },
+        ]
+    );
+
+    let actions = plan_copy(&source, &home, &CopyPolicy::Overwrite, None, None).unwrap();
+    assert!(actions.contains(&CopyAction::Overwrite {
+        source: source.join(".gitconfig"),
+        target: home.join(".gitconfig"),
+    }));
+
+    copy_file(&source.join(".gitconfig"), &home.join(".gitconfig")).unwrap();
+    let actions = plan_copy(&source, &home, &CopyPolicy::Overwrite, None, None).unwrap();
+    assert!(actions.contains(&CopyAction::Skip {
+        source: source.join(".gitconfig"),
+        target: home.join(".gitconfig"),
+        reason: "unchanged",
+    }));
+
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[test]
+fn test_copy_read_only_file() {
+    let root = std::env::temp_dir().join(format!("metl-copies-ro-{}", std::process::id()));
+    let source = root.join("id_ed25519.pub");
+    let target = root.join("copied");
+
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    fs::write(&source, "ssh-ed25519 AAAA").unwrap();
+    fs::set_permissions(&source, Permissions::from_mode(0o444)).unwrap();
+
+    copy_file(&source, &target).unwrap();
+
+    let source_metadata = fs::metadata(&source).unwrap();
+    let target_metadata = fs::metadata(&target).unwrap();
+    assert_eq!(target_metadata.permissions().mode() & 0o777, 0o444);
+    assert_eq!(
+        target_metadata.modified().unwrap(),
+        source_metadata.modified().unwrap()
+    );
+
+    // NOTE: a second sync replaces the read-only copy
+    copy_file(&source, &target).unwrap();
+    assert_eq!(fs::read(&target).unwrap(), b"ssh-ed25519 AAAA");
+
+    let _ = fs::remove_dir_all(&root);
+}
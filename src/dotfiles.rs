@@ -0,0 +1,667 @@
+use std::{
+    env,
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use colored::control::SHOULD_COLORIZE;
+
+use crate::{
+    backups::{
+        BackedUpFile, get_backups_path, list_backup_sessions, read_restore_log, restore_file,
+    },
+    commits::commit_dotfiles,
+    config::{Config, get_home_path, load_config},
+    copies::{
+        CopyAction, CopyPolicy, RenderContext, copy_tree, is_rendered, is_unchanged, move_path,
+        plan_copy, render_file,
+    },
+    errors::{
+        dotfiles_dir_read_error, dotfiles_package_not_deployed, failed_reading_restore_log,
+        missing_dotfiles_checkout, no_backups_found,
+    },
+    ignores::IgnoreRules,
+    privileges::PrivateDir,
+    state::{DeployedFile, DeployedKind, DotfilesState, PackageState, owning_package},
+    successes::{
+        backup_session_listed, dotfile_adopted, dotfile_diff, dotfile_removed, dotfile_restored,
+        dotfile_status,
+    },
+    symlinks::{LinkAction, apply_action, normalize, plan_package},
+    warnings::{
+        warn_dotfile_adopt_failed, warn_dotfile_diff_failed, warn_dotfile_remove_failed,
+        warn_dotfile_restore_failed, warn_dotfiles_state_not_saved,
+    },
+};
+
+pub fn restore_backup(id: Option<String>, list: bool) {
+    let sessions = list_backup_sessions();
+
+    if list {
+        sessions.iter().for_each(|id| {
+            let files = read_restore_log(&get_backups_path().join(id))
+                .map(|log| log.files.len())
+                .unwrap_or_default();
+
+            backup_session_listed(id, files);
+        });
+
+        return;
+    }
+
+    let Some(id) = id.or_else(|| sessions.last().cloned()) else {
+        no_backups_found();
+    };
+
+    let session_dir = get_backups_path().join(&id);
+    let log = match read_restore_log(&session_dir) {
+        Ok(log) => log,
+        Err(error) => failed_reading_restore_log(error, session_dir),
+    };
+
+    let mut state = DotfilesState::load();
+    let render = RenderContext::new(&load_config());
+    let mut restored_all = true;
+
+    // NOTE: restore in reverse so nested backups land after their parents
+    for file in log.files.iter().rev() {
+        let restored = match replaced_by_metl(file, &state, &render) {
+            Ok(_) => restore_file(file).map_err(|error| error.to_string()),
+            Err(reason) => Err(reason),
+        };
+
+        match restored {
+            Ok(_) => {
+                state.forget(&file.original);
+                dotfile_restored(&file.original, &file.backup);
+            }
+            Err(reason) => {
+                restored_all = false;
+                warn_dotfile_restore_failed(&file.original, &reason);
+            }
+        }
+    }
+
+    if let Err(error) = state.save() {
+        warn_dotfiles_state_not_saved(error);
+    }
+
+    if restored_all {
+        let _ = fs::remove_dir_all(&session_dir);
+    }
+}
+
+/// Checks that whatever is at the original path of a backup is still the
+/// link or copy metl deployed there, so restoring it loses nothing.
+/// Directories are only removed once empty, so they pass.
+fn replaced_by_metl(
+    file: &BackedUpFile,
+    state: &DotfilesState,
+    render: &RenderContext,
+) -> Result<(), String> {
+    let Ok(metadata) = fs::symlink_metadata(&file.original) else {
+        return Ok(());
+    };
+
+    if metadata.is_dir() {
+        return Ok(());
+    }
+
+    let deployed = state
+        .packages
+        .values()
+        .flat_map(|package| &package.files)
+        .find(|deployed| deployed.target == file.original);
+
+    match deployed {
+        Some(deployed) => match deployed_changed(deployed, &metadata, render) {
+            Some(reason) => Err(reason.to_string()),
+            None => Ok(()),
+        },
+        None => Err("was not deployed by metl".to_string()),
+    }
+}
+
+/// Why the link or copy at `file.target` is no longer the one metl
+/// deployed, if it was changed since.
+fn deployed_changed(
+    file: &DeployedFile,
+    metadata: &fs::Metadata,
+    render: &RenderContext,
+) -> Option<&'static str> {
+    let target = &file.target;
+
+    match file.kind {
+        DeployedKind::Link => {
+            let parent = target.parent().unwrap_or(Path::new("/"));
+            let dest = fs::read_link(target)
+                .map(|link| normalize(&parent.join(link)))
+                .ok();
+
+            match metadata.is_symlink() && dest.as_ref() == file.source.as_ref() {
+                true => None,
+                false => Some("was changed since it was linked"),
+            }
+        }
+
+        DeployedKind::Copy => {
+            let unchanged = file
+                .source
+                .as_deref()
+                .is_some_and(|source| is_unchanged(source, target, Some(render)));
+
+            match unchanged {
+                true => None,
+                false => Some("differs from the dotfiles package"),
+            }
+        }
+
+        DeployedKind::Dir => None,
+    }
+}
+
+/// Moves `files` from `$HOME` into `package` in the dotfiles checkout and
+/// puts a link (or, in copy mode, the original) back in their place.
+pub fn adopt(package: String, files: Vec<PathBuf>, commit: bool) {
+    let config = load_config();
+    let home_dir = get_home_path();
+    let dotfiles_path = config.dotfiles_path();
+    let package_path = dotfiles_path.join(&package);
+
+    if !dotfiles_path.join(".git").exists() {
+        missing_dotfiles_checkout(dotfiles_path);
+    }
+
+    let current_dir = env::current_dir().unwrap_or_else(|_| home_dir.clone());
+    let mut adopted: Vec<String> = vec![];
+    let mut destinations: Vec<PathBuf> = vec![];
+    let mut state = DotfilesState::load();
+
+    for file in files {
+        let original = normalize(&current_dir.join(&file));
+
+        let Ok(relative) = original.strip_prefix(&home_dir) else {
+            warn_dotfile_adopt_failed(&original, "is not inside the home directory");
+            continue;
+        };
+
+        let Ok(metadata) = fs::symlink_metadata(&original) else {
+            warn_dotfile_adopt_failed(&original, "does not exist");
+            continue;
+        };
+
+        if metadata.is_symlink() {
+            warn_dotfile_adopt_failed(&original, "is already a symlink");
+            continue;
+        }
+
+        let destination = package_path.join(relative);
+        if fs::symlink_metadata(&destination).is_ok() {
+            warn_dotfile_adopt_failed(&original, "already exists in the dotfiles package");
+            continue;
+        }
+
+        if let Err(error) = adopt_file(&original, &destination, config.dotfiles_symlink) {
+            warn_dotfile_adopt_failed(&original, &error.to_string());
+            continue;
+        }
+
+        if config.dotfiles_symlink {
+            state.record(&package, &original, Some(&destination), DeployedKind::Link);
+        }
+
+        dotfile_adopted(&original, &destination);
+        adopted.push(relative.to_string_lossy().to_string());
+        destinations.push(destination);
+    }
+
+    if config.dotfiles_symlink
+        && !adopted.is_empty()
+        && let Err(error) = state.save()
+    {
+        warn_dotfiles_state_not_saved(error);
+    }
+
+    if commit && !adopted.is_empty() {
+        let commit_msg = format!("Adopted into {package}: {}", adopted.join(" "));
+        commit_dotfiles(&dotfiles_path, &destinations, &commit_msg);
+    }
+}
+
+fn adopt_file(original: &Path, destination: &Path, symlink: bool) -> io::Result<()> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if !symlink {
+        return copy_tree(original, destination);
+    }
+
+    move_path(original, destination)?;
+
+    apply_action(&LinkAction::CreateLink {
+        target: original.to_path_buf(),
+        source: destination.to_path_buf(),
+    })
+}
+
+/// Undoes what `metl sync` deployed for `package`, leaving alone anything
+/// changed since and moving backed up originals back into place.
+pub fn remove_package(package: String, dry_run: bool) {
+    let mut state = DotfilesState::load();
+
+    let Some(deployed) = state.packages.remove(&package) else {
+        dotfiles_package_not_deployed(&package);
+    };
+
+    let render = RenderContext::new(&load_config());
+    let mut kept: Vec<DeployedFile> = vec![];
+
+    // NOTE: remove in reverse so files go before the directories holding them
+    for file in deployed.files.into_iter().rev() {
+        match remove_deployed(&file, &render, dry_run) {
+            Ok(true) => dotfile_removed(&file.target, file.backup.as_deref(), dry_run),
+            Ok(false) => {}
+            Err(reason) => {
+                warn_dotfile_remove_failed(&file.target, &reason);
+                kept.push(file);
+            }
+        }
+    }
+
+    if dry_run {
+        return;
+    }
+
+    if !kept.is_empty() {
+        kept.reverse();
+        state.packages.insert(package, PackageState { files: kept });
+    }
+
+    if let Err(error) = state.save() {
+        warn_dotfiles_state_not_saved(error);
+    }
+}
+
+/// Removes a single deployed file if it is still the one metl put there,
+/// returning whether anything was removed. Directories are only removed
+/// once empty.
+fn remove_deployed(
+    file: &DeployedFile,
+    render: &RenderContext,
+    dry_run: bool,
+) -> Result<bool, String> {
+    let target = &file.target;
+
+    if let Ok(metadata) = fs::symlink_metadata(target) {
+        if let Some(reason) = deployed_changed(file, &metadata, render) {
+            return Err(reason.to_string());
+        }
+
+        if let DeployedKind::Dir = file.kind {
+            let is_empty = fs::read_dir(target)
+                .map(|mut entries| entries.next().is_none())
+                .unwrap_or(false);
+
+            if !metadata.is_dir() || !is_empty {
+                return match file.backup {
+                    Some(_) => Err("directory is not empty".to_string()),
+                    None => Ok(false),
+                };
+            }
+        }
+    }
+
+    if dry_run {
+        return Ok(true);
+    }
+
+    if let Some(backup) = &file.backup {
+        let backed_up = BackedUpFile {
+            original: target.clone(),
+            backup: backup.clone(),
+        };
+
+        return restore_file(&backed_up)
+            .map(|_| true)
+            .map_err(|error| error.to_string());
+    }
+
+    match fs::symlink_metadata(target) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir(target),
+        Ok(_) => fs::remove_file(target),
+        Err(_) => Ok(()),
+    }
+    .map(|_| true)
+    .map_err(|error| error.to_string())
+}
+
+/// Where a single file of a dotfile package stands relative to `$HOME`.
+#[derive(Debug, PartialEq, Eq)]
+enum FileStatus {
+    Linked,
+    Missing,
+    Conflict,
+    Unchanged,
+    Modified,
+}
+
+impl FileStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            FileStatus::Linked => "linked",
+            FileStatus::Missing => "missing",
+            FileStatus::Conflict => "conflict",
+            FileStatus::Unchanged => "unchanged",
+            FileStatus::Modified => "modified",
+        }
+    }
+}
+
+struct PackageFile {
+    target: PathBuf,
+    source: PathBuf,
+    status: FileStatus,
+}
+
+pub fn status(packages: Vec<String>) {
+    let config = load_config();
+    let home_dir = get_home_path();
+
+    for package in dotfile_packages(&config, packages) {
+        let name = package.to_string_lossy();
+
+        package_files(&config, &package, &home_dir)
+            .iter()
+            .for_each(|file| dotfile_status(&name, &file.target, file.status.as_str()));
+    }
+}
+
+/// Shows how files in `$HOME` differ from the dotfiles repo, for copies
+/// that were edited in place and for files in the way of a link.
+pub fn diff(packages: Vec<String>) {
+    let config = load_config();
+    let render = RenderContext::new(&config);
+    let home_dir = get_home_path();
+
+    for package in dotfile_packages(&config, packages) {
+        let differing = package_files(&config, &package, &home_dir)
+            .into_iter()
+            .filter(|file| matches!(file.status, FileStatus::Modified | FileStatus::Conflict))
+            .filter(|file| file.target.is_file() && file.source.is_file());
+
+        for file in differing {
+            match diff_package_file(&file, &render) {
+                Ok(diff) => dotfile_diff(&file.target, &file.source, &diff),
+                Err(error) => warn_dotfile_diff_failed(&file.target, error),
+            }
+        }
+    }
+}
+
+/// The requested packages, or the ones sync applies on this host when none
+/// are given.
+fn dotfile_packages(config: &Config, packages: Vec<String>) -> Vec<OsString> {
+    let dotfiles_path = config.dotfiles_path();
+
+    if !dotfiles_path.join(".git").exists() {
+        missing_dotfiles_checkout(dotfiles_path);
+    }
+
+    if !packages.is_empty() {
+        return packages.into_iter().map(OsString::from).collect();
+    }
+
+    if let Some(selected) = config.selected_dotfiles() {
+        return selected.iter().map(OsString::from).collect();
+    }
+
+    let entries = match fs::read_dir(&dotfiles_path) {
+        Ok(entries) => entries,
+        Err(error) => dotfiles_dir_read_error(dotfiles_path, error, true),
+    };
+
+    let ignores = IgnoreRules::load(&dotfiles_path);
+    let mut packages: Vec<OsString> = entries
+        .flatten()
+        .filter(|entry| entry.path().is_dir() && !ignores.is_ignored(&entry.path(), true))
+        .map(|entry| entry.file_name())
+        .filter(|name| !name.to_string_lossy().starts_with('.'))
+        .collect();
+    packages.sort();
+
+    packages
+}
+
+fn package_files(config: &Config, package: &OsString, home_dir: &Path) -> Vec<PackageFile> {
+    let dotfiles_path = config.dotfiles_path();
+    let package_path = dotfiles_path.join(package);
+
+    let source_of =
+        |target: &Path| package_path.join(target.strip_prefix(home_dir).unwrap_or(target));
+
+    let render = RenderContext::new(config);
+    let ignores = IgnoreRules::load(&dotfiles_path);
+    let copies = match plan_copy(
+        &package_path,
+        home_dir,
+        &CopyPolicy::Overwrite,
+        Some(&render),
+        Some(&ignores),
+    ) {
+        Ok(actions) => actions,
+        Err(error) => dotfiles_dir_read_error(package_path, error, true),
+    };
+    let copied = copies.into_iter().filter_map(copied_file);
+
+    if !config.dotfiles_symlink {
+        return copied.collect();
+    }
+
+    let actions = match plan_package(&dotfiles_path, package, home_dir, &ignores, false) {
+        Ok(actions) => actions,
+        Err(error) => dotfiles_dir_read_error(package_path, error, true),
+    };
+
+    let name = package.to_string_lossy();
+    let owned = |source: &Path| owning_package(&dotfiles_path, source).as_deref() == Some(&name);
+
+    let linked_file = |target: PathBuf, status: FileStatus| PackageFile {
+        source: source_of(&target),
+        target,
+        status,
+    };
+
+    // NOTE: unfolding relinks other packages' files, those are not ours
+    let linked = actions.into_iter().filter_map(|action| match action {
+        LinkAction::AlreadyLinked { target, .. } => Some(linked_file(target, FileStatus::Linked)),
+        LinkAction::CreateLink { target, source } if owned(&source) => {
+            Some(linked_file(target, FileStatus::Missing))
+        }
+        LinkAction::Conflict { target, .. } => Some(linked_file(target, FileStatus::Conflict)),
+        _ => None,
+    });
+
+    // NOTE: templates and secrets are copied in symlink mode too
+    linked
+        .chain(copied.filter(|file| is_rendered(&file.source)))
+        .collect()
+}
+
+fn copied_file(action: CopyAction) -> Option<PackageFile> {
+    let (source, target, status) = match action {
+        CopyAction::Create { source, target } => (source, target, FileStatus::Missing),
+        CopyAction::Overwrite { source, target } => (source, target, FileStatus::Modified),
+        CopyAction::Skip {
+            source,
+            target,
+            reason: "unchanged",
+        } => (source, target, FileStatus::Unchanged),
+        CopyAction::Skip { source, target, .. } => (source, target, FileStatus::Conflict),
+        CopyAction::CreateDir { .. } | CopyAction::Prompt { .. } => return None,
+    };
+
+    Some(PackageFile {
+        target,
+        source,
+        status,
+    })
+}
+
+/// Diffs against the rendered contents for templates and secrets, labelled
+/// with the source's path.
+fn diff_package_file(file: &PackageFile, render: &RenderContext) -> io::Result<String> {
+    if !is_rendered(&file.source) {
+        return diff_files(&file.target, &file.source);
+    }
+
+    let rendered = render_file(&file.source, render)?;
+
+    // NOTE: the rendered file may be a decrypted secret
+    let private_dir = PrivateDir::new("rendered")?;
+    let rendered_path = private_dir.join(file.target.file_name().unwrap_or_default());
+
+    File::options()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&rendered_path)?
+        .write_all(&rendered)?;
+
+    let diff = diff_files(&file.target, &rendered_path);
+
+    let label = |path: &Path| path.to_string_lossy().trim_start_matches('/').to_string();
+    diff.map(|diff| diff.replace(&label(&rendered_path), &label(&file.source)))
+}
+
+pub fn diff_files(target: &Path, source: &Path) -> io::Result<String> {
+    let color = match SHOULD_COLORIZE.should_colorize() {
+        true => "--color=always",
+        false => "--color=never",
+    };
+
+    let output = Command::new("git")
+        .args(["diff", "--no-index", color, "--"])
+        .arg(target)
+        .arg(source)
+        .output()?;
+
+    // NOTE: git diff exits with 1 when the files differ
+    match output.status.code() {
+        Some(0 | 1) => Ok(String::from_utf8_lossy(&output.stdout).to_string()),
+        _ => Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        )),
+    }
+}
+
+#[test]
+fn test_adopt_file() {
+    let root = env::temp_dir().join(format!("metl-adopt-{}", std::process::id()));
+    let original = root.join("home/.config/nvim/init.lua");
+    let linked = root.join("dotfiles/nvim/.config/nvim/init.lua");
+    let copied = root.join("dotfiles/nvim-copy/.config/nvim/init.lua");
+
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(original.parent().unwrap()).unwrap();
+    fs::write(&original, "vim.o.number = true").unwrap();
+
+    adopt_file(&original, &copied, false).unwrap();
+    assert!(!fs::symlink_metadata(&original).unwrap().is_symlink());
+    assert_eq!(fs::read_to_string(&copied).unwrap(), "vim.o.number = true");
+
+    adopt_file(&original, &linked, true).unwrap();
+    assert!(fs::symlink_metadata(&original).unwrap().is_symlink());
+    assert_eq!(
+        normalize(
+            &original
+                .parent()
+                .unwrap()
+                .join(fs::read_link(&original).unwrap())
+        ),
+        linked
+    );
+    assert_eq!(
+        fs::read_to_string(&original).unwrap(),
+        "vim.o.number = true"
+    );
+
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[test]
+fn test_package_files_status_and_diff() {
+    let root = env::temp_dir().join(format!("metl-dotfiles-status-{}", std::process::id()));
+    let package = root.join("dotfiles/shell");
+    let home = root.join("home");
+
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&package).unwrap();
+    fs::create_dir_all(&home).unwrap();
+
+    fs::write(package.join(".profile"), "export EDITOR=nvim\n").unwrap();
+    fs::write(home.join(".profile"), "export EDITOR=nvim\n").unwrap();
+    fs::write(package.join(".bashrc"), "set -o vi\n").unwrap();
+    fs::write(home.join(".bashrc"), "set -o emacs\n").unwrap();
+    fs::write(package.join(".inputrc"), "set editing-mode vi\n").unwrap();
+    fs::write(package.join(".mailrc.tmpl"), "set from={{ email }}\n").unwrap();
+    fs::write(home.join(".mailrc"), "set from=old@example.com\n").unwrap();
+
+    let mut config = Config {
+        dotfiles_dir: root.join("dotfiles").to_string_lossy().to_string(),
+        variables: [("email".to_string(), "me@example.com".to_string())].into(),
+        ..Config::default()
+    };
+    let render = RenderContext::new(&config);
+    let name = OsString::from("shell");
+
+    let statuses = |config: &Config| -> Vec<(String, FileStatus)> {
+        let mut statuses: Vec<(String, FileStatus)> = package_files(config, &name, &home)
+            .into_iter()
+            .map(|file| {
+                let target = file.target.strip_prefix(&home).unwrap();
+                (target.to_string_lossy().to_string(), file.status)
+            })
+            .collect();
+        statuses.sort_by(|a, b| a.0.cmp(&b.0));
+        statuses
+    };
+
+    assert_eq!(
+        statuses(&config),
+        vec![
+            (".bashrc".to_string(), FileStatus::Modified),
+            (".inputrc".to_string(), FileStatus::Missing),
+            (".mailrc".to_string(), FileStatus::Modified),
+            (".profile".to_string(), FileStatus::Unchanged),
+        ]
+    );
+
+    let mailrc = package_files(&config, &name, &home)
+        .into_iter()
+        .find(|file| file.target.ends_with(".mailrc"))
+        .unwrap();
+    let diff = diff_package_file(&mailrc, &render).unwrap();
+    assert!(diff.contains("-set from=old@example.com"));
+    assert!(diff.contains("+set from=me@example.com"));
+    assert!(diff.contains(".mailrc.tmpl"));
+
+    config.dotfiles_symlink = true;
+    std::os::unix::fs::symlink(package.join(".inputrc"), home.join(".inputrc")).unwrap();
+
+    assert_eq!(
+        statuses(&config),
+        vec![
+            (".bashrc".to_string(), FileStatus::Conflict),
+            (".inputrc".to_string(), FileStatus::Linked),
+            (".mailrc".to_string(), FileStatus::Modified),
+            (".profile".to_string(), FileStatus::Conflict),
+        ]
+    );
+
+    let _ = fs::remove_dir_all(&root);
+}
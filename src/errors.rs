@@ -1,9 +1,18 @@
 use core::panic;
-use std::{io::Error, path::PathBuf, sync::LazyLock};
+use std::{
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
 
 use colored::{ColoredString, Colorize};
 
-use crate::{manifest::PackageManager, sync::RestoreError};
+use crate::{
+    checkout::RestoreError,
+    manifest::PackageManager,
+    output::{Event, emit, is_json},
+    pacman_db::PacmanDbError,
+    validation::Diagnostic,
+};
 
 static ERROR: LazyLock<ColoredString> = LazyLock::new(|| "[ERROR]".red().bold());
 
@@ -16,6 +25,16 @@ pub fn missing_metl_config(metl_config_path: PathBuf) -> ! {
     );
 }
 
+pub fn config_parsing_error(diagnostic: Diagnostic, config_path: PathBuf) -> ! {
+    panic!(
+        "{} {} {}\n\t{}",
+        &*ERROR,
+        "Invalid metl config at".white().dimmed(),
+        config_path.to_string_lossy().white().bold(),
+        diagnostic.to_string().cyan()
+    );
+}
+
 pub fn unsupported_package_manager(package_manager: &PackageManager) -> ! {
     panic!(
         "{} {} {}",
@@ -33,21 +52,57 @@ pub fn manifest_serialization_error() -> ! {
     )
 }
 
-pub fn packages_list_error(error: Option<Error>) -> ! {
+pub fn pacman_db_error(error: PacmanDbError) -> ! {
     panic!(
-        "{} {}\n\t{:?}",
+        "{} {}\n\t{}",
         &*ERROR,
-        "Could not get package list".red().dimmed(),
-        error
+        "Could not read the pacman database".red().dimmed(),
+        error.to_string().cyan().dimmed()
     )
 }
 
-pub fn packages_parsing_error(manager: PackageManager) -> ! {
+pub fn failed_reading_pacman_log(error: std::io::Error, log_path: PathBuf) -> ! {
     panic!(
-        "{} {} {}",
+        "{} {} {}\n\t{}",
         &*ERROR,
-        "Could not parse package output bytes from".white().dimmed(),
-        manager.to_string().white().bold()
+        "Could not read pacman log at path:".white().dimmed(),
+        log_path.to_string_lossy().white().bold(),
+        error.to_string().cyan().dimmed()
+    );
+}
+
+pub fn hook_write_failed(hook_path: &Path, error: std::io::Error) -> ! {
+    panic!(
+        "{} {} {}\n\t{}",
+        &*ERROR,
+        "Could not update the pacman hook at".white().dimmed(),
+        hook_path.to_string_lossy().white().bold(),
+        error.to_string().cyan().dimmed()
+    );
+}
+
+pub fn hook_config_dir_unknown() -> ! {
+    panic!(
+        "{} {}\n\t{}",
+        &*ERROR,
+        "Can not tell whose metl config the pacman hook should update as root"
+            .white()
+            .dimmed(),
+        "run it through sudo or doas, or pass --config-dir"
+            .cyan()
+            .dimmed()
+    );
+}
+
+pub fn missing_manifest_repo(config_path: PathBuf) -> ! {
+    panic!(
+        "{} {} {}\n\t{}",
+        &*ERROR,
+        "No manifest repo at".white().dimmed(),
+        config_path.to_string_lossy().white().bold(),
+        "initialize it with git init and add an origin remote first"
+            .cyan()
+            .dimmed()
     );
 }
 
@@ -71,21 +126,13 @@ pub fn manifest_parsing_error(error: &toml::de::Error, manifest_path: PathBuf) -
     );
 }
 
-pub fn dotfiles_clone_error(error: RestoreError, verbose: bool) -> ! {
-    if verbose {
-        panic!(
-            "{} {}\n{}",
-            &*ERROR,
-            "dotfiles could not be cloned".white().dimmed(),
-            error.to_string().white().bold()
-        )
-    } else {
-        panic!(
-            "{} {}",
-            &*ERROR,
-            "dotfiles could not be cloned".white().dimmed()
-        )
-    }
+pub fn dotfiles_clone_error(error: RestoreError) -> ! {
+    panic!(
+        "{} {}\n\t{}",
+        &*ERROR,
+        "dotfiles could not be cloned or updated".white().dimmed(),
+        error.to_string().white().bold()
+    )
 }
 
 pub fn dotfiles_dir_read_error(dotfiles_path: PathBuf, error: std::io::Error, verbose: bool) -> ! {
@@ -118,6 +165,15 @@ pub fn package_install_failed(package: &str, error: std::io::Error) -> ! {
 }
 
 pub fn install_failed(package_manager: &PackageManager, installed: &str, code: i32) {
+    if is_json() {
+        return emit(Event::PackageFailed {
+            manager: package_manager.to_string(),
+            package: installed.to_string(),
+            code: Some(code),
+            error: None,
+        });
+    }
+
     println!(
         "{} {} {} {}, code: {}",
         &*ERROR,
@@ -129,6 +185,15 @@ pub fn install_failed(package_manager: &PackageManager, installed: &str, code: i
 }
 
 pub fn remove_failed(package_manager: &PackageManager, installed: &str, code: i32) {
+    if is_json() {
+        return emit(Event::PackageFailed {
+            manager: package_manager.to_string(),
+            package: installed.to_string(),
+            code: Some(code),
+            error: None,
+        });
+    }
+
     println!(
         "{} {} {} {}, code: {}",
         &*ERROR,
@@ -147,3 +212,165 @@ pub fn missing_prerequirements(missing: &[String]) {
         missing.join(" ").white().bold()
     );
 }
+
+pub fn failed_reading_config(error: std::io::Error, config_path: PathBuf) -> ! {
+    panic!(
+        "{} {} {}\n\t{}",
+        &*ERROR,
+        "Could not read metl config at path:".white().dimmed(),
+        config_path.to_string_lossy().white().bold(),
+        error.to_string().cyan().dimmed()
+    );
+}
+
+pub fn failed_writing_config(error: std::io::Error, config_path: PathBuf) -> ! {
+    panic!(
+        "{} {} {}\n\t{}",
+        &*ERROR,
+        "Could not write metl config at path:".white().dimmed(),
+        config_path.to_string_lossy().white().bold(),
+        error.to_string().cyan().dimmed()
+    );
+}
+
+pub fn unknown_config_key(key: &str, valid_keys: &[String]) -> ! {
+    panic!(
+        "{} {} {}\n\t{} {}",
+        &*ERROR,
+        "Unknown config key:".white().dimmed(),
+        key.white().bold(),
+        "valid keys are:".white().dimmed(),
+        valid_keys.join(", ").cyan()
+    );
+}
+
+pub fn invalid_config_value(key: &str, value: &str, reason: &str) -> ! {
+    panic!(
+        "{} {} {} {}\n\t{}",
+        &*ERROR,
+        "Invalid value for".white().dimmed(),
+        key.white().bold(),
+        value.cyan().bold(),
+        reason.cyan().dimmed()
+    );
+}
+
+pub fn invalid_config(reason: &str) -> ! {
+    panic!(
+        "{} {}\n\t{}",
+        &*ERROR,
+        "Config is invalid, no changes were saved:".white().dimmed(),
+        reason.cyan().dimmed()
+    );
+}
+
+pub fn config_key_not_set(key: &str) -> ! {
+    panic!(
+        "{} {} {}",
+        &*ERROR,
+        "Config key is not set:".white().dimmed(),
+        key.white().bold()
+    );
+}
+
+pub fn editor_failed(editor: &str, error: Option<std::io::Error>) -> ! {
+    match error {
+        Some(error) => panic!(
+            "{} {} {}\n\t{}",
+            &*ERROR,
+            "Could not run editor:".white().dimmed(),
+            editor.white().bold(),
+            error.to_string().cyan().dimmed()
+        ),
+        None => panic!(
+            "{} {} {}",
+            &*ERROR,
+            "Editor exited with an error:".white().dimmed(),
+            editor.white().bold()
+        ),
+    }
+}
+
+pub fn no_backups_found() -> ! {
+    panic!(
+        "{} {}",
+        &*ERROR,
+        "No dotfile backups found to restore".white().dimmed(),
+    );
+}
+
+pub fn failed_reading_restore_log(error: std::io::Error, backup_path: PathBuf) -> ! {
+    panic!(
+        "{} {} {}\n\t{}",
+        &*ERROR,
+        "Could not read restore log in".white().dimmed(),
+        backup_path.to_string_lossy().white().bold(),
+        error.to_string().cyan().dimmed()
+    );
+}
+
+pub fn missing_dotfiles_checkout(dotfiles_path: PathBuf) -> ! {
+    panic!(
+        "{} {} {}\n\t{}",
+        &*ERROR,
+        "No dotfiles checkout at".white().dimmed(),
+        dotfiles_path.to_string_lossy().white().bold(),
+        "run metl sync first".cyan().dimmed()
+    );
+}
+
+pub fn dotfiles_package_not_deployed(package: &str) -> ! {
+    panic!(
+        "{} {} {}\n\t{}",
+        &*ERROR,
+        "No deployed files recorded for dotfiles package"
+            .white()
+            .dimmed(),
+        package.white().bold(),
+        "only files linked or copied by metl sync can be removed"
+            .cyan()
+            .dimmed()
+    );
+}
+
+pub fn missing_secrets_identity(identity: &Path) -> ! {
+    panic!(
+        "{} {} {}\n\t{} {}",
+        &*ERROR,
+        "No age identity found at".white().dimmed(),
+        identity.to_string_lossy().white().bold(),
+        "create one with age-keygen -o".cyan().dimmed(),
+        identity.to_string_lossy().cyan().dimmed()
+    );
+}
+
+pub fn invalid_secret_file(path: &Path, reason: &str) -> ! {
+    panic!(
+        "{} {} {}",
+        &*ERROR,
+        path.to_string_lossy().white().bold(),
+        reason.white().dimmed(),
+    );
+}
+
+pub fn secret_failed(path: &Path, action: &str, error: std::io::Error) -> ! {
+    panic!(
+        "{} {} {}\n\t{}",
+        &*ERROR,
+        format!("Could not {action}").white().dimmed(),
+        path.to_string_lossy().white().bold(),
+        error.to_string().cyan().dimmed()
+    );
+}
+
+pub fn secret_edit_not_saved(path: &Path, kept: &Path, error: std::io::Error) -> ! {
+    panic!(
+        "{} {} {}\n\t{}\n\t{} {}",
+        &*ERROR,
+        "Could not encrypt".white().dimmed(),
+        path.to_string_lossy().white().bold(),
+        error.to_string().cyan().dimmed(),
+        "the edited plaintext was kept at".cyan().dimmed(),
+        kept.to_string_lossy().cyan().bold()
+    );
+}
@@ -0,0 +1,349 @@
+use std::{
+    collections::HashMap,
+    env,
+    fs::{self, File},
+    io::{self, Write},
+    os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    commits::commit_metl_files,
+    config::{Config, get_config_path, load_config},
+    dotfiles::diff_files,
+    errors::pacman_db_error,
+    output::prompt_confirm,
+    pacman_db::{PACMAN_DB_PATH, backup_files},
+    privileges::{Escalation, PrivateDir, escalated_command, run_escalated},
+    successes::{etc_file_diff, etc_file_restored, etc_file_status, etc_file_tracked},
+    symlinks::normalize,
+    warnings::warn_etc_file_failed,
+};
+
+/// Where tracked `/etc` files live in the manifest repo, mirroring their
+/// paths below `/`.
+fn get_etc_path() -> PathBuf {
+    get_config_path().join("etc")
+}
+
+fn tracked_path(system_path: &Path) -> PathBuf {
+    get_config_path().join(system_path.strip_prefix("/").unwrap_or(system_path))
+}
+
+/// Reads a system file, through the escalation tool when only root can.
+fn read_system_file(path: &Path, escalation: &Escalation) -> io::Result<Vec<u8>> {
+    match fs::read(path) {
+        Err(error) if error.kind() == io::ErrorKind::PermissionDenied => {
+            let output = escalated_command("cat", escalation).arg(path).output()?;
+
+            match output.status.success() {
+                true => Ok(output.stdout),
+                false => Err(io::Error::other(
+                    String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                )),
+            }
+        }
+        read => read,
+    }
+}
+
+/// Every file in the tracked `etc/` dir, as the system path it restores.
+fn tracked_files() -> Vec<PathBuf> {
+    let mut pending = vec![get_etc_path()];
+    let mut tracked = vec![];
+
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            match entry.path() {
+                path if path.is_dir() => pending.push(path),
+                path => {
+                    if let Ok(relative) = path.strip_prefix(get_config_path()) {
+                        tracked.push(Path::new("/").join(relative));
+                    }
+                }
+            }
+        }
+    }
+
+    tracked.sort();
+    tracked
+}
+
+/// Lists the backup files edited since their package installed them and
+/// the tracked ones, with whether the tracked copy still matches.
+pub fn etc_list() {
+    let backups = match backup_files(Path::new(PACMAN_DB_PATH)) {
+        Ok(backups) => backups,
+        Err(error) => pacman_db_error(error),
+    };
+
+    let tracked = tracked_files();
+    let owners: HashMap<&Path, &str> = backups
+        .iter()
+        .map(|backup| (backup.path.as_path(), backup.package.as_str()))
+        .collect();
+
+    for backup in backups
+        .iter()
+        .filter(|backup| backup.path.starts_with("/etc"))
+    {
+        if tracked.contains(&backup.path) {
+            continue;
+        }
+
+        match fs::read(&backup.path) {
+            Ok(contents) if backup.is_modified(&contents) => {
+                etc_file_status(&backup.path, &backup.package, "modified")
+            }
+            Ok(_) => {}
+            Err(error) if error.kind() == io::ErrorKind::PermissionDenied => {
+                etc_file_status(&backup.path, &backup.package, "unreadable")
+            }
+            Err(_) => {}
+        }
+    }
+
+    for path in &tracked {
+        let package = owners.get(path.as_path()).copied().unwrap_or_default();
+
+        let status = match (fs::read(path), fs::read(tracked_path(path))) {
+            (Ok(system), Ok(copy)) if system == copy => "tracked",
+            (Ok(_), Ok(_)) => "drifted",
+            (Err(error), _) if error.kind() == io::ErrorKind::NotFound => "missing",
+            _ => "unreadable",
+        };
+
+        etc_file_status(path, package, status);
+    }
+}
+
+/// Copies `files` from `/etc` into the tracked `etc/` dir, refreshing the
+/// ones already tracked. Files other users can not read may hold secrets and
+/// are pushed with the manifest repo, so they need `allow_private` and their
+/// copy stays readable by the owner only.
+pub fn etc_add(files: Vec<PathBuf>, allow_private: bool, commit: bool) {
+    let config = load_config();
+    let current_dir = env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
+    let mut added: Vec<String> = vec![];
+
+    for file in files {
+        let path = normalize(&current_dir.join(&file));
+
+        if !path.starts_with("/etc") || path == Path::new("/etc") {
+            warn_etc_file_failed(&path, "is not a file inside /etc");
+            continue;
+        }
+
+        let private = match fs::metadata(&path) {
+            Ok(metadata) => metadata.permissions().mode() & 0o004 == 0,
+            Err(error) => {
+                warn_etc_file_failed(&path, &error.to_string());
+                continue;
+            }
+        };
+
+        if private && !allow_private {
+            warn_etc_file_failed(
+                &path,
+                "is not world-readable, pass --allow-private to track it anyway",
+            );
+            continue;
+        }
+
+        let copied = read_system_file(&path, &config.escalation)
+            .and_then(|contents| write_tracked_copy(&tracked_path(&path), &contents, private));
+
+        if let Err(error) = copied {
+            warn_etc_file_failed(&path, &error.to_string());
+            continue;
+        }
+
+        etc_file_tracked(&path, true);
+        added.push(path.to_string_lossy().to_string());
+    }
+
+    if commit && !added.is_empty() {
+        commit_metl_files(&format!("Tracked {}", added.join(" ")));
+    }
+}
+
+fn write_tracked_copy(destination: &Path, contents: &[u8], private: bool) -> io::Result<()> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mode = match private {
+        true => 0o600,
+        false => 0o644,
+    };
+
+    // NOTE: a private file is never briefly readable, even when refreshed
+    let mut file = File::options()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(destination)?;
+    file.set_permissions(fs::Permissions::from_mode(mode))?;
+    file.write_all(contents)
+}
+
+/// Stops tracking `files`, leaving them in `/etc` as they are.
+pub fn etc_remove(files: Vec<PathBuf>, commit: bool) {
+    let current_dir = env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
+    let mut removed: Vec<String> = vec![];
+
+    for file in files {
+        let path = normalize(&current_dir.join(&file));
+        let tracked = tracked_path(&path);
+
+        if !path.starts_with("/etc") || !tracked.is_file() {
+            warn_etc_file_failed(&path, "is not tracked");
+            continue;
+        }
+
+        if let Err(error) = fs::remove_file(&tracked) {
+            warn_etc_file_failed(&path, &error.to_string());
+            continue;
+        }
+
+        // NOTE: drop directories the removal left empty, stopping at etc/
+        let etc_path = get_etc_path();
+        for dir in tracked.ancestors().skip(1) {
+            if dir == etc_path || fs::remove_dir(dir).is_err() {
+                break;
+            }
+        }
+
+        etc_file_tracked(&path, false);
+        removed.push(path.to_string_lossy().to_string());
+    }
+
+    if commit && !removed.is_empty() {
+        commit_metl_files(&format!("Untracked {}", removed.join(" ")));
+    }
+}
+
+/// Writes the tracked files that differ back into `/etc` as root, showing
+/// the diff and asking before each one.
+pub fn restore_etc_files(config: &Config, dry_run: bool) {
+    for path in tracked_files() {
+        let tracked = tracked_path(&path);
+
+        let current = match read_system_file(&path, &config.escalation) {
+            Ok(current) => Some(current),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+            Err(error) => {
+                warn_etc_file_failed(&path, &error.to_string());
+                continue;
+            }
+        };
+
+        if current.as_ref() == fs::read(&tracked).ok().as_ref() {
+            continue;
+        }
+
+        match diff_system_file(&path, current.as_deref().unwrap_or_default(), &tracked) {
+            Ok(diff) => etc_file_diff(&path, &diff),
+            Err(error) => {
+                warn_etc_file_failed(&path, &error.to_string());
+                continue;
+            }
+        }
+
+        if dry_run {
+            etc_file_restored(&path, true);
+            continue;
+        }
+
+        if !prompt_confirm(&format!("restore {}?", path.to_string_lossy())) {
+            continue;
+        }
+
+        match install_system_file(&tracked, &path, &config.escalation) {
+            Ok(_) => etc_file_restored(&path, false),
+            Err(error) => warn_etc_file_failed(&path, &error.to_string()),
+        }
+    }
+}
+
+/// Diffs the current contents of `path` against its tracked copy, through a
+/// private temp dir since only root may be able to read the original.
+fn diff_system_file(path: &Path, current: &[u8], tracked: &Path) -> io::Result<String> {
+    let private_dir = PrivateDir::new("etc")?;
+    let current_path = private_dir.join(path.file_name().unwrap_or_default());
+
+    File::options()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&current_path)?
+        .write_all(current)?;
+
+    let diff = diff_files(&current_path, tracked);
+
+    let label = |path: &Path| path.to_string_lossy().trim_start_matches('/').to_string();
+    diff.map(|diff| diff.replace(&label(&current_path), &label(path)))
+}
+
+/// Installs `source` as `target`, keeping the owner, group and mode of the
+/// file it replaces. New files are owned by root:root with the mode of
+/// `source`, so a private tracked copy stays private.
+pub fn install_system_file(
+    source: &Path,
+    target: &Path,
+    escalation: &Escalation,
+) -> io::Result<()> {
+    let (mode, uid, gid) = installed_owner(source, target)?;
+
+    run_escalated(
+        escalated_command("install", escalation)
+            .arg("-D")
+            .arg("-m")
+            .arg(format!("{mode:o}"))
+            .arg("-o")
+            .arg(uid.to_string())
+            .arg("-g")
+            .arg(gid.to_string())
+            .arg(source)
+            .arg(target),
+    )
+}
+
+/// The mode, uid and gid `install_system_file` gives `target`.
+fn installed_owner(source: &Path, target: &Path) -> io::Result<(u32, u32, u32)> {
+    match fs::metadata(target) {
+        Ok(metadata) => Ok((
+            metadata.permissions().mode() & 0o7777,
+            metadata.uid(),
+            metadata.gid(),
+        )),
+        Err(_) => Ok((fs::metadata(source)?.permissions().mode() & 0o777, 0, 0)),
+    }
+}
+
+#[test]
+fn test_private_tracked_copy() {
+    let root = std::env::temp_dir().join(format!("metl-etc-files-{}", std::process::id()));
+    let copy = root.join("etc/sudoers.d/wheel");
+
+    let _ = fs::remove_dir_all(&root);
+    write_tracked_copy(&copy, b"%wheel ALL=(ALL:ALL) ALL\n", true).unwrap();
+    assert_eq!(
+        fs::metadata(&copy).unwrap().permissions().mode() & 0o777,
+        0o600
+    );
+    assert_eq!(
+        installed_owner(&copy, &root.join("missing/sudoers.d/wheel")).unwrap(),
+        (0o600, 0, 0)
+    );
+
+    write_tracked_copy(&copy, b"[options]\n", false).unwrap();
+    assert_eq!(
+        fs::metadata(&copy).unwrap().permissions().mode() & 0o777,
+        0o644
+    );
+    assert_eq!(fs::read(&copy).unwrap(), b"[options]\n");
+
+    let _ = fs::remove_dir_all(&root);
+}
@@ -1,26 +1,26 @@
 use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
     fs,
-    process::{self, Command},
+    path::Path,
 };
 
+use toml::value::Datetime;
+
 use crate::{
     config::{Config, get_config_path, load_config},
-    errors::{
-        manifest_serialization_error, missing_prerequirements, packages_list_error,
-        packages_parsing_error,
-    },
+    errors::{manifest_serialization_error, pacman_db_error},
     manifest::{
-        Manifest, Package,
+        Group, Manifest, Package,
         PackageManager::{self, Pacman, Paru, Yay},
+        load_manifest,
     },
+    pacman_db::{InstallReason, PACMAN_DB_PATH, SyncDatabases, installed_packages},
+    services::enabled_services,
     successes::packages_retrieved_successfully,
-    sync::check_if_available,
-    warnings::warn_package_output,
 };
 
 pub fn generate() {
     let config = load_config();
-    check_prereqs(&config);
 
     let Config {
         package_manager,
@@ -28,26 +28,45 @@ pub fn generate() {
         ..
     } = config;
 
-    let mut manifest = Manifest { packages: vec![] };
+    // NOTE: repositories are declared by hand, so they survive regenerating,
+    // and packages keep the install date they were first listed with
+    let mut previous = match get_config_path().join("manifest.toml").exists() {
+        true => load_manifest(),
+        false => Manifest::default(),
+    };
+
+    let previous_packages = std::mem::take(&mut previous.packages);
+    let mut manifest = Manifest {
+        repositories: previous.repositories,
+        groups: vec![],
+        packages: vec![],
+        services: enabled_services(previous.services),
+    };
 
     match package_manager {
-        Paru => get_arch_packages(package_manager, &mut manifest, locked_versions),
-        Pacman => get_arch_packages(package_manager, &mut manifest, locked_versions),
-        Yay => get_arch_packages(package_manager, &mut manifest, locked_versions),
+        Paru => get_arch_packages(
+            package_manager,
+            &mut manifest,
+            &previous_packages,
+            locked_versions,
+        ),
+        Pacman => get_arch_packages(
+            package_manager,
+            &mut manifest,
+            &previous_packages,
+            locked_versions,
+        ),
+        Yay => get_arch_packages(
+            package_manager,
+            &mut manifest,
+            &previous_packages,
+            locked_versions,
+        ),
     }
 
     write_manifest(manifest);
 }
 
-pub fn check_prereqs(config: &Config) {
-    let mut missing: Vec<String> = vec![];
-    check_if_available(&config.package_manager.to_string(), &mut missing);
-
-    if !missing.is_empty() {
-        missing_prerequirements(&missing);
-    }
-}
-
 fn write_manifest(manifest: Manifest) {
     let Ok(manifest_output) = toml::to_string_pretty(&manifest) else {
         manifest_serialization_error();
@@ -57,56 +76,181 @@ fn write_manifest(manifest: Manifest) {
     let _ = fs::write(manifest_path, manifest_output);
 }
 
-fn get_arch_packages(manager: PackageManager, manifest: &mut Manifest, locked_versions: bool) {
-    let flags = match manager {
-        Pacman | Yay | Paru => "-Qe",
+/// Reads explicitly installed packages straight from pacman's local
+/// database, the AUR helpers share it with pacman. Groups with every member
+/// installed explicitly replace their members, unless versions are locked
+/// and the members have to stay pinned.
+fn get_arch_packages(
+    manager: PackageManager,
+    manifest: &mut Manifest,
+    previous: &[Package],
+    locked_versions: bool,
+) {
+    let db_path = Path::new(PACMAN_DB_PATH);
+    let sync = SyncDatabases::load(db_path);
+
+    let installed = match installed_packages(db_path, &sync) {
+        Ok(installed) => installed,
+        Err(error) => pacman_db_error(error),
     };
 
-    let mut command = Command::new(manager.to_string());
-    command.arg(flags);
+    manifest.packages = installed
+        .into_iter()
+        .filter(|package| package.reason == InstallReason::Explicit)
+        .map(|package| Package {
+            installed_at: package.installed_at(),
+            name: package.name,
+            version: locked_versions.then_some(package.version),
+            repository: package.repository,
+            packager: package.packager,
+        })
+        .collect();
+
+    keep_installed_at(&mut manifest.packages, previous);
 
-    let list_cmd_result = command
-        .stdout(process::Stdio::piped())
-        .stderr(process::Stdio::piped())
-        .output();
+    if !locked_versions {
+        manifest.groups = collapse_groups(&mut manifest.packages, &sync.groups);
+    }
 
-    let output = match list_cmd_result {
-        Ok(output) => output,
-        Err(error) => packages_list_error(Some(error)),
-    };
+    packages_retrieved_successfully(manager);
+}
 
-    if !output.status.success() {
-        packages_list_error(None);
+/// Keeps the `installed_at` of packages `previous` already listed, pacman
+/// resets its install date on every upgrade.
+fn keep_installed_at(packages: &mut [Package], previous: &[Package]) {
+    let installed_at: BTreeMap<&str, Datetime> = previous
+        .iter()
+        .filter_map(|package| Some((package.name.as_str(), package.installed_at?)))
+        .collect();
+
+    for package in packages {
+        if let Some(&date) = installed_at.get(package.name.as_str()) {
+            package.installed_at = Some(date);
+        }
     }
+}
+
+/// Returns the groups whose members are all in `packages`, removing those
+/// members from it. Their install dates, repositories and packagers are
+/// dropped, the group stands for all of them.
+pub fn collapse_groups(
+    packages: &mut Vec<Package>,
+    groups: &BTreeMap<String, BTreeSet<String>>,
+) -> Vec<Group> {
+    let explicit: HashSet<&str> = packages
+        .iter()
+        .map(|package| package.name.as_str())
+        .collect();
 
-    let Ok(packages) = String::from_utf8(output.stdout) else {
-        packages_parsing_error(manager);
+    let complete: Vec<(&String, &BTreeSet<String>)> = groups
+        .iter()
+        .filter(|(_, members)| {
+            !members.is_empty()
+                && members
+                    .iter()
+                    .all(|member| explicit.contains(member.as_str()))
+        })
+        .collect();
+
+    let grouped: HashSet<String> = complete
+        .iter()
+        .flat_map(|(_, members)| members.iter().cloned())
+        .collect();
+    packages.retain(|package| !grouped.contains(&package.name));
+
+    complete
+        .into_iter()
+        .map(|(name, _)| Group { name: name.clone() })
+        .collect()
+}
+
+#[test]
+fn test_collapse_groups() {
+    let package = |name: &str| Package {
+        name: name.to_string(),
+        version: None,
+        installed_at: Some("2024-06-01T18:40:00Z".parse().unwrap()),
+        repository: Some("core".to_string()),
+        packager: Some("Arch Linux <https://archlinux.org>".to_string()),
+    };
+    let group = |members: &[&str]| -> BTreeSet<String> {
+        members.iter().map(|member| member.to_string()).collect()
     };
 
-    let package_list: Vec<&str> = packages
-        .split('\n')
-        .filter(|p| !p.trim().is_empty())
+    let mut packages = vec![
+        package("autoconf"),
+        package("automake"),
+        package("gcc"),
+        package("neovim"),
+        package("plasma-desktop"),
+    ];
+    let groups = BTreeMap::from([
+        (
+            "base-devel".to_string(),
+            group(&["autoconf", "automake", "gcc"]),
+        ),
+        ("plasma".to_string(), group(&["kwin", "plasma-desktop"])),
+    ]);
+
+    let collapsed = collapse_groups(&mut packages, &groups);
+
+    assert_eq!(
+        collapsed,
+        vec![Group {
+            name: "base-devel".to_string()
+        }]
+    );
+    assert_eq!(packages, vec![package("neovim"), package("plasma-desktop")]);
+
+    let manifest = Manifest {
+        repositories: vec![],
+        groups: collapsed,
+        packages,
+        services: vec![],
+    };
+
+    let rendered = toml::to_string_pretty(&manifest).unwrap();
+    assert!(rendered.contains("[[groups]]\nname = \"base-devel\"\n\n[[packages]]"));
+    assert!(!rendered.contains("gcc"));
+
+    let expanded: Vec<String> = manifest
+        .expanded_packages(&groups)
+        .into_iter()
+        .map(|package| package.name)
         .collect();
 
-    let mut packages: Vec<Package> = vec![];
-
-    for p in package_list {
-        let Some((name, version)) = p.split_once(" ") else {
-            warn_package_output(p);
-            continue;
-        };
-
-        packages.push(Package {
-            name: name.into(),
-            version: if locked_versions {
-                Some(version.into())
-            } else {
-                None
-            },
-        })
-    }
+    assert_eq!(
+        expanded,
+        vec!["neovim", "plasma-desktop", "autoconf", "automake", "gcc"]
+    );
+}
 
-    manifest.packages = packages;
+#[test]
+fn test_keep_installed_at() {
+    let package = |name: &str, installed_at: Option<&str>| Package {
+        name: name.to_string(),
+        installed_at: installed_at.map(|date| date.parse().unwrap()),
+        ..Package::default()
+    };
 
-    packages_retrieved_successfully(manager);
+    let previous = vec![
+        package("neovim", Some("2021-03-14T09:12:00Z")),
+        package("htop", None),
+    ];
+    let mut packages = vec![
+        package("neovim", Some("2024-06-01T18:40:00Z")),
+        package("htop", Some("2024-06-01T18:40:00Z")),
+        package("ripgrep", Some("2024-06-02T08:05:00Z")),
+    ];
+
+    keep_installed_at(&mut packages, &previous);
+
+    assert_eq!(
+        packages,
+        vec![
+            package("neovim", Some("2021-03-14T09:12:00Z")),
+            package("htop", Some("2024-06-01T18:40:00Z")),
+            package("ripgrep", Some("2024-06-02T08:05:00Z")),
+        ]
+    );
 }
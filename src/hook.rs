@@ -0,0 +1,337 @@
+use std::{
+    collections::BTreeSet,
+    env, fs,
+    io::{self, BufRead},
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::{
+    commits::commit_metl_files,
+    config::{get_config_path, get_user_config_path, is_config_dir_chosen, load_config},
+    errors::{hook_config_dir_unknown, hook_write_failed},
+    generate::generate,
+    manifest::Manifest,
+    privileges::{PrivateDir, escalated_command, is_root, run_escalated},
+    state::get_state_path,
+    successes::{hook_installed, hook_removed},
+};
+
+pub const HOOK_PATH: &str = "/etc/pacman.d/hooks/metl.hook";
+
+/// Written by `metl install`, `metl remove` and `metl sync` while pacman
+/// runs, so the hook leaves the manifest to them.
+const PROXY_MARKER: &str = "proxied-transaction";
+
+/// How many targets a hook commit message names before summarizing the rest.
+const NAMED_TARGETS: usize = 5;
+
+/// Quotes an `Exec` argument for ALPM's word splitting when needed.
+fn quote(argument: &str) -> String {
+    match argument.contains(char::is_whitespace) || argument.contains(['"', '\'', '\\']) {
+        true => format!("'{}'", argument.replace('\'', "'\\''")),
+        false => argument.to_string(),
+    }
+}
+
+/// The user whose manifest the hook updates. Hooks run as root, so the
+/// hook switches back to whoever installed it.
+fn hook_user() -> Option<String> {
+    let user = match is_root() {
+        true => env::var("SUDO_USER").or_else(|_| env::var("DOAS_USER")),
+        false => env::var("USER"),
+    };
+
+    user.ok().filter(|user| !user.is_empty() && user != "root")
+}
+
+/// The home directory `passwd` lists for `user`.
+fn passwd_home(passwd: &str, user: &str) -> Option<PathBuf> {
+    passwd.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+
+        match fields.as_slice() {
+            [name, _, _, _, _, home, ..] if *name == user => Some(PathBuf::from(home)),
+            _ => None,
+        }
+    })
+}
+
+/// The config dir the hook updates. As root it belongs to the user who ran
+/// sudo or doas, root's own config is never the one they meant.
+fn hook_config_path(user: Option<&str>) -> PathBuf {
+    if !is_root() || is_config_dir_chosen() {
+        return get_config_path();
+    }
+
+    let home = user.and_then(|user| {
+        let passwd = fs::read_to_string("/etc/passwd").ok()?;
+        passwd_home(&passwd, user)
+    });
+
+    match home {
+        Some(home) => get_user_config_path(home),
+        None => hook_config_dir_unknown(),
+    }
+}
+
+fn render_hook(metl: &Path, config_path: &Path, user: Option<&str>) -> String {
+    let mut exec = vec![];
+
+    if let Some(user) = user {
+        exec.extend(["/usr/bin/runuser", "-u", user, "--"].map(String::from));
+    }
+
+    exec.push(metl.to_string_lossy().to_string());
+    exec.push("--config-dir".to_string());
+    exec.push(config_path.to_string_lossy().to_string());
+    exec.extend(["hook", "run"].map(String::from));
+
+    let exec: Vec<String> = exec.iter().map(|argument| quote(argument)).collect();
+
+    format!(
+        "# Written by metl hook install, remove with metl hook remove
+[Trigger]
+Operation = Install
+Operation = Upgrade
+Operation = Remove
+Type = Package
+Target = *
+
+[Action]
+Description = Updating the metl manifest...
+When = PostTransaction
+Exec = {}
+NeedsTargets
+",
+        exec.join(" ")
+    )
+}
+
+/// Writes an ALPM hook that regenerates and commits the manifest after
+/// every pacman transaction, including ones run without metl.
+pub fn hook_install() {
+    let hook_path = Path::new(HOOK_PATH);
+
+    let metl = match env::current_exe() {
+        Ok(metl) => metl,
+        Err(error) => hook_write_failed(hook_path, error),
+    };
+
+    let user = hook_user();
+    let hook = render_hook(&metl, &hook_config_path(user.as_deref()), user.as_deref());
+
+    if is_root() {
+        let written = hook_path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(hook_path, &hook));
+
+        if let Err(error) = written {
+            hook_write_failed(hook_path, error);
+        }
+
+        return hook_installed(hook_path);
+    }
+
+    let config = load_config();
+    let private_dir = match PrivateDir::new("hook") {
+        Ok(private_dir) => private_dir,
+        Err(error) => hook_write_failed(hook_path, error),
+    };
+
+    let staged = private_dir.join("metl.hook");
+    if let Err(error) = fs::write(&staged, &hook) {
+        hook_write_failed(hook_path, error);
+    }
+
+    let mut command = escalated_command("install", &config.escalation);
+    command
+        .arg("-D")
+        .arg("-m")
+        .arg("644")
+        .arg(&staged)
+        .arg(hook_path);
+
+    if let Err(error) = run_escalated(&mut command) {
+        hook_write_failed(hook_path, error);
+    }
+
+    hook_installed(hook_path);
+}
+
+pub fn hook_remove() {
+    let config = load_config();
+    let hook_path = Path::new(HOOK_PATH);
+    let existed = hook_path.exists();
+
+    if existed {
+        let removed = match is_root() {
+            true => fs::remove_file(hook_path),
+            false => run_escalated(
+                escalated_command("rm", &config.escalation)
+                    .arg("-f")
+                    .arg(hook_path),
+            ),
+        };
+
+        if let Err(error) = removed {
+            hook_write_failed(hook_path, error);
+        }
+    }
+
+    hook_removed(hook_path, existed);
+}
+
+fn manifest_packages() -> BTreeSet<String> {
+    fs::read_to_string(get_config_path().join("manifest.toml"))
+        .ok()
+        .and_then(|contents| toml::from_str::<Manifest>(contents.trim()).ok())
+        .map(|manifest| {
+            let groups = manifest.groups.into_iter().map(|group| group.name);
+            let packages = manifest.packages.into_iter().map(|package| package.name);
+
+            groups.chain(packages).collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Lists up to `NAMED_TARGETS` packages, counting the rest.
+fn summarize_targets(targets: &[String]) -> String {
+    let named = targets
+        .iter()
+        .take(NAMED_TARGETS)
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    match targets.len().saturating_sub(NAMED_TARGETS) {
+        0 => named,
+        rest => format!("{named} and {rest} more"),
+    }
+}
+
+/// The hook commit message, naming what the transaction added to and
+/// removed from the manifest, or the targets when it only changed versions.
+fn hook_commit_msg(
+    before: &BTreeSet<String>,
+    after: &BTreeSet<String>,
+    targets: &[String],
+) -> String {
+    let added: Vec<String> = after.difference(before).cloned().collect();
+    let removed: Vec<String> = before.difference(after).cloned().collect();
+
+    let mut changes = vec![];
+    if !added.is_empty() {
+        changes.push(format!("installed {}", summarize_targets(&added)));
+    }
+
+    if !removed.is_empty() {
+        changes.push(format!("removed {}", summarize_targets(&removed)));
+    }
+
+    if changes.is_empty() {
+        changes.push(format!("upgraded {}", summarize_targets(targets)));
+    }
+
+    format!("Updated by pacman hook: {}", changes.join("; "))
+}
+
+/// Whether a metl command still running started this transaction, either
+/// committing it itself or syncing the system to the manifest.
+fn is_proxied_transaction() -> bool {
+    fs::read_to_string(get_state_path().join(PROXY_MARKER))
+        .ok()
+        .and_then(|pid| pid.trim().parse::<u32>().ok())
+        .is_some_and(|pid| Path::new(&format!("/proc/{pid}")).exists())
+}
+
+/// Marks the transactions pacman runs for metl until dropped.
+pub struct ProxiedTransaction(PathBuf);
+
+impl ProxiedTransaction {
+    pub fn start() -> Self {
+        let state_path = get_state_path();
+        let marker = state_path.join(PROXY_MARKER);
+
+        let _ = fs::create_dir_all(&state_path)
+            .and_then(|_| fs::write(&marker, process::id().to_string()));
+
+        ProxiedTransaction(marker)
+    }
+}
+
+impl Drop for ProxiedTransaction {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Run by the ALPM hook with the transaction targets on stdin.
+pub fn hook_run() {
+    let targets: Vec<String> = io::stdin()
+        .lock()
+        .lines()
+        .map_while(Result::ok)
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    if is_proxied_transaction() {
+        return;
+    }
+
+    let before = fs::read_to_string(get_config_path().join("manifest.toml")).unwrap_or_default();
+    let before_packages = manifest_packages();
+
+    generate();
+
+    let after = fs::read_to_string(get_config_path().join("manifest.toml")).unwrap_or_default();
+    if after == before {
+        return;
+    }
+
+    commit_metl_files(&hook_commit_msg(
+        &before_packages,
+        &manifest_packages(),
+        &targets,
+    ));
+}
+
+#[test]
+fn test_hook_commit_msg() {
+    let packages = |names: &[&str]| -> BTreeSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    };
+    let targets: Vec<String> = (1..=7).map(|n| format!("lib{n}")).collect();
+
+    assert_eq!(
+        hook_commit_msg(
+            &packages(&["vim"]),
+            &packages(&["htop", "neovim"]),
+            &targets
+        ),
+        "Updated by pacman hook: installed htop, neovim; removed vim"
+    );
+    assert_eq!(
+        hook_commit_msg(&packages(&["vim"]), &packages(&["vim"]), &targets),
+        "Updated by pacman hook: upgraded lib1, lib2, lib3, lib4, lib5 and 2 more"
+    );
+
+    let hook = render_hook(
+        Path::new("/usr/bin/metl"),
+        Path::new("/home/jane/my config/metl"),
+        Some("jane"),
+    );
+    assert!(hook.contains(
+        "Exec = /usr/bin/runuser -u jane -- /usr/bin/metl --config-dir '/home/jane/my config/metl' hook run\n"
+    ));
+    assert!(hook.contains("\nNeedsTargets\n"));
+
+    let passwd = "root:x:0:0::/root:/bin/bash\njane:x:1000:1000:Jane:/home/jane:/bin/zsh\n";
+    assert_eq!(
+        passwd_home(passwd, "jane"),
+        Some(PathBuf::from("/home/jane"))
+    );
+    assert_eq!(passwd_home(passwd, "joe"), None);
+}
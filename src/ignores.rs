@@ -0,0 +1,140 @@
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use ignore::{
+    Match,
+    gitignore::{Gitignore, GitignoreBuilder},
+};
+
+use crate::warnings::warn_metlignore_invalid;
+
+const IGNORE_FILE: &str = ".metlignore";
+
+/// What deploying a dotfile package leaves out: the names stow skips by
+/// default plus `.metlignore` files in gitignore syntax, one at the root of
+/// the dotfiles repo for every package and one inside each package. Package
+/// patterns win over repo patterns, and a `!` pattern brings back a name
+/// skipped by default, like `!README.md`.
+pub struct IgnoreRules {
+    dotfiles_path: PathBuf,
+    repo: Gitignore,
+    packages: HashMap<OsString, Gitignore>,
+}
+
+/// Names stow skips by default, `root_only` ones only at the package root.
+fn is_default_ignored(name: &OsStr, root: bool) -> bool {
+    let name = name.to_string_lossy();
+
+    let always = matches!(
+        name.as_ref(),
+        ".git" | ".gitignore" | ".gitmodules" | IGNORE_FILE
+    ) || name.ends_with('~')
+        || name.starts_with(".#")
+        || (name.starts_with('#') && name.ends_with('#'));
+
+    let root_only = name.starts_with("README") || name.starts_with("LICENSE") || name == "COPYING";
+
+    always || (root && root_only)
+}
+
+fn load_ignore_file(dir: &Path) -> Gitignore {
+    let path = dir.join(IGNORE_FILE);
+    if !path.is_file() {
+        return Gitignore::empty();
+    }
+
+    let mut builder = GitignoreBuilder::new(dir);
+    if let Some(error) = builder.add(&path) {
+        warn_metlignore_invalid(&path, error);
+    }
+
+    builder.build().unwrap_or_else(|error| {
+        warn_metlignore_invalid(&path, error);
+        Gitignore::empty()
+    })
+}
+
+impl IgnoreRules {
+    pub fn load(dotfiles_path: &Path) -> Self {
+        let packages = fs::read_dir(dotfiles_path)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| entry.path().join(IGNORE_FILE).is_file())
+            .map(|entry| (entry.file_name(), load_ignore_file(&entry.path())))
+            .collect();
+
+        IgnoreRules {
+            dotfiles_path: dotfiles_path.to_path_buf(),
+            repo: load_ignore_file(dotfiles_path),
+            packages,
+        }
+    }
+
+    /// Whether `source`, a path inside one of the packages, is left out.
+    pub fn is_ignored(&self, source: &Path, is_dir: bool) -> bool {
+        let Ok(relative) = source.strip_prefix(&self.dotfiles_path) else {
+            return false;
+        };
+
+        let mut components = relative.components();
+        let Some(package) = components.next() else {
+            return false;
+        };
+
+        // NOTE: a package itself can only be ignored from the repo
+        let inside = components.next().is_some();
+        let root = inside && components.next().is_none();
+
+        let package_match = match self.packages.get(package.as_os_str()) {
+            Some(rules) if inside => rules.matched(source, is_dir),
+            _ => Match::None,
+        };
+
+        match package_match.or(self.repo.matched(source, is_dir)) {
+            Match::Ignore(_) => true,
+            Match::Whitelist(_) => false,
+            Match::None => {
+                inside
+                    && source
+                        .file_name()
+                        .is_some_and(|name| is_default_ignored(name, root))
+            }
+        }
+    }
+}
+
+#[test]
+fn test_ignore_rules() {
+    let root = std::env::temp_dir().join(format!("metl-ignores-{}", std::process::id()));
+    let dotfiles = root.join("dotfiles");
+
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(dotfiles.join("nvim/.config/nvim")).unwrap();
+    fs::create_dir_all(dotfiles.join("scripts")).unwrap();
+    fs::write(dotfiles.join(IGNORE_FILE), "*.swp\n/scripts/\n").unwrap();
+    fs::write(
+        dotfiles.join("nvim").join(IGNORE_FILE),
+        "!README.md\nlazy-lock.json\n",
+    )
+    .unwrap();
+
+    let rules = IgnoreRules::load(&dotfiles);
+    let ignored = |path: &str, is_dir| rules.is_ignored(&dotfiles.join(path), is_dir);
+
+    assert!(ignored("scripts", true));
+    assert!(!ignored("nvim", true));
+    assert!(ignored("nvim/.config/nvim/.init.lua.swp", false));
+    assert!(ignored("nvim/.config/nvim/lazy-lock.json", false));
+    assert!(ignored("nvim/.metlignore", false));
+    assert!(!ignored("nvim/README.md", false));
+    assert!(!ignored("nvim/.config/nvim/init.lua", false));
+    assert!(ignored("zsh/README.md", false));
+    assert!(!ignored("zsh/.config/README.md", false));
+
+    let _ = fs::remove_dir_all(&root);
+}
@@ -0,0 +1,403 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    commits::{commit_metl_file_at, push_metl_files},
+    config::get_config_path,
+    errors::{
+        failed_reading_pacman_log, manifest_serialization_error, missing_manifest_repo,
+        pacman_db_error,
+    },
+    generate::collapse_groups,
+    manifest::{Manifest, Package},
+    pacman_db::{InstallReason, PACMAN_DB_PATH, SyncDatabases, installed_packages},
+    successes::{manifest_history, manifest_history_imported},
+    warnings::warn_manifest_not_written,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+enum LogAction {
+    Installed,
+    Removed,
+    Upgraded,
+    Downgraded,
+    Reinstalled,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct LogEntry {
+    action: LogAction,
+    package: String,
+}
+
+/// The changes pacman logged for one command, timestamped with the first of
+/// them.
+#[derive(Debug, PartialEq, Eq)]
+struct Transaction {
+    timestamp: String,
+    command: Option<String>,
+    entries: Vec<LogEntry>,
+}
+
+/// How the explicitly installed packages changed in one transaction.
+#[derive(Debug, PartialEq, Eq)]
+pub struct HistoryStep {
+    pub timestamp: String,
+    pub command: Option<String>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    packages: BTreeSet<String>,
+}
+
+/// Splits `[timestamp] [source] message`, older logs have no source.
+fn split_line(line: &str) -> Option<(&str, &str, &str)> {
+    let (timestamp, rest) = line.strip_prefix('[')?.split_once(']')?;
+    let rest = rest.trim_start();
+
+    match rest.strip_prefix('[').and_then(|rest| rest.split_once(']')) {
+        Some((source, message)) => Some((timestamp, source, message.trim())),
+        None => Some((timestamp, "", rest.trim())),
+    }
+}
+
+fn parse_entry(message: &str) -> Option<LogEntry> {
+    let (action, rest) = message.split_once(' ')?;
+    let action = match action {
+        "installed" => LogAction::Installed,
+        "removed" => LogAction::Removed,
+        "upgraded" => LogAction::Upgraded,
+        "downgraded" => LogAction::Downgraded,
+        "reinstalled" => LogAction::Reinstalled,
+        _ => return None,
+    };
+
+    // NOTE: only `name (version)` lines, hooks and scriptlets log other text
+    let (package, version) = rest.split_once(' ')?;
+    version.starts_with('(').then(|| LogEntry {
+        action,
+        package: package.to_string(),
+    })
+}
+
+/// Groups the package changes in a pacman log by the command that made them.
+fn parse_log(log: &str) -> Vec<Transaction> {
+    let mut transactions: Vec<Transaction> = vec![];
+    let mut command = None;
+    let mut open = false;
+
+    for (timestamp, source, message) in log.lines().filter_map(split_line) {
+        if let Some(running) = message.strip_prefix("Running '") {
+            command = Some(running.trim_end_matches('\'').to_string());
+            open = false;
+            continue;
+        }
+
+        match message {
+            "transaction started" => {
+                open = false;
+                continue;
+            }
+            "transaction completed" | "transaction failed" => {
+                open = false;
+                command = None;
+                continue;
+            }
+            _ => {}
+        }
+
+        // NOTE: install scriptlets can print lines that look like entries
+        if source == "ALPM-SCRIPTLET" {
+            continue;
+        }
+
+        let Some(entry) = parse_entry(message) else {
+            continue;
+        };
+
+        if !open {
+            transactions.push(Transaction {
+                timestamp: timestamp.to_string(),
+                command: command.clone(),
+                entries: vec![],
+            });
+            open = true;
+        }
+
+        if let Some(transaction) = transactions.last_mut() {
+            transaction.entries.push(entry);
+        }
+    }
+
+    transactions
+}
+
+/// Replays the log, keeping packages that are explicitly installed today or
+/// were named on the command line that installed them. Transactions that
+/// only touched dependencies or upgraded packages are left out.
+fn replay(transactions: Vec<Transaction>, explicit: &HashSet<String>) -> Vec<HistoryStep> {
+    let mut packages = BTreeSet::new();
+    let mut steps = vec![];
+
+    for transaction in transactions {
+        let targets: HashSet<&str> = transaction
+            .command
+            .iter()
+            .flat_map(|command| command.split_whitespace().skip(1))
+            .filter(|argument| !argument.starts_with('-'))
+            .collect();
+
+        let mut added = vec![];
+        let mut removed = vec![];
+
+        for entry in &transaction.entries {
+            let name = &entry.package;
+
+            match entry.action {
+                LogAction::Installed | LogAction::Reinstalled
+                    if (explicit.contains(name) || targets.contains(name.as_str()))
+                        && packages.insert(name.clone()) =>
+                {
+                    added.push(name.clone())
+                }
+                LogAction::Removed if packages.remove(name) => removed.push(name.clone()),
+                _ => {}
+            }
+        }
+
+        if added.is_empty() && removed.is_empty() {
+            continue;
+        }
+
+        steps.push(HistoryStep {
+            timestamp: transaction.timestamp,
+            command: transaction.command,
+            added,
+            removed,
+            packages: packages.clone(),
+        });
+    }
+
+    steps
+}
+
+/// Reconstructs when explicit packages were added and removed from
+/// `log_path`, as a report or, with `commit`, as backdated commits of the
+/// manifest followed by one restoring the current manifest.
+pub fn import_log(log_path: PathBuf, commit: bool) {
+    let log = match fs::read_to_string(&log_path) {
+        Ok(log) => log,
+        Err(error) => failed_reading_pacman_log(error, log_path),
+    };
+
+    // NOTE: only install reasons are needed, so the sync databases are skipped
+    let sync = SyncDatabases::default();
+    let explicit: HashSet<String> = match installed_packages(Path::new(PACMAN_DB_PATH), &sync) {
+        Ok(installed) => installed
+            .into_iter()
+            .filter(|package| package.reason == InstallReason::Explicit)
+            .map(|package| package.name)
+            .collect(),
+        Err(error) => pacman_db_error(error),
+    };
+
+    let steps = replay(parse_log(&log), &explicit);
+
+    if !commit {
+        return steps.iter().for_each(manifest_history);
+    }
+
+    let config_path = get_config_path();
+    if !config_path.join(".git").exists() {
+        missing_manifest_repo(config_path);
+    }
+
+    let manifest_path = config_path.join("manifest.toml");
+    // NOTE: without a manifest there is nothing to restore, the last imported
+    // step is kept instead
+    let current = fs::read_to_string(&manifest_path).ok();
+    let template = current
+        .as_deref()
+        .and_then(|current| toml::from_str::<Manifest>(current.trim()).ok())
+        .unwrap_or_default();
+    let sync = SyncDatabases::load(Path::new(PACMAN_DB_PATH));
+
+    let mut written = String::new();
+    let mut imported = 0;
+
+    for step in &steps {
+        written = render_manifest(&step.packages, &template, &sync);
+        if let Err(error) = fs::write(&manifest_path, &written) {
+            warn_manifest_not_written(error);
+            break;
+        }
+
+        let commit_msg = match &step.command {
+            Some(command) => format!("Imported from pacman.log: {command}"),
+            None => "Imported from pacman.log".to_string(),
+        };
+
+        let date = git_date(&step.timestamp, local_offset);
+        if !commit_metl_file_at(&manifest_path, &commit_msg, Some(&date)) {
+            break;
+        }
+
+        imported += 1;
+    }
+
+    if let Some(current) = current {
+        let _ = fs::write(&manifest_path, &current);
+        if imported > 0 && written != current {
+            commit_metl_file_at(&manifest_path, "Restored the current manifest", None);
+        }
+    }
+
+    push_metl_files();
+    manifest_history_imported(imported);
+}
+
+/// The manifest as it was after a step, with the repositories and services
+/// of the current manifest and groups collapsed the way `generate` does.
+fn render_manifest(
+    packages: &BTreeSet<String>,
+    template: &Manifest,
+    sync: &SyncDatabases,
+) -> String {
+    let mut packages: Vec<Package> = packages
+        .iter()
+        .map(|name| Package {
+            name: name.clone(),
+            ..Package::default()
+        })
+        .collect();
+
+    let manifest = Manifest {
+        repositories: template.repositories.clone(),
+        groups: collapse_groups(&mut packages, &sync.groups),
+        packages,
+        services: template.services.clone(),
+    };
+
+    match toml::to_string_pretty(&manifest) {
+        Ok(rendered) => rendered,
+        Err(_) => manifest_serialization_error(),
+    }
+}
+
+/// The UTC offset local time had at a legacy `YYYY-MM-DD HH:MM` timestamp,
+/// asked from `date` so daylight saving time is taken into account.
+fn local_offset(timestamp: &str) -> Option<String> {
+    let output = Command::new("date")
+        .arg("-d")
+        .arg(timestamp)
+        .arg("+%:z")
+        .output()
+        .ok()?;
+
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Converts a log timestamp to RFC 3339, which git reads for both the author
+/// and committer date. Current logs look like `2023-01-05T10:22:31+0100`,
+/// legacy ones like `2013-01-05 10:22` are in local time without an offset.
+fn git_date(timestamp: &str, local_offset: impl Fn(&str) -> Option<String>) -> String {
+    if let Some((date_time, offset)) = timestamp
+        .rsplit_once(['+', '-'])
+        .filter(|(date_time, _)| date_time.contains('T'))
+    {
+        let sign = &timestamp[date_time.len()..=date_time.len()];
+        let (hours, minutes) = offset.split_at(offset.len().min(2));
+        let minutes = minutes.trim_start_matches(':');
+
+        return format!("{date_time}{sign}{hours}:{minutes:0>2}");
+    }
+
+    let (date, time) = timestamp.split_once(' ').unwrap_or((timestamp, "00:00"));
+    let seconds = match time.len() {
+        5 => ":00",
+        _ => "",
+    };
+    let offset = local_offset(timestamp).unwrap_or_else(|| "+00:00".to_string());
+
+    format!("{date}T{time}{seconds}{offset}")
+}
+
+#[test]
+fn test_replay_pacman_log() {
+    let log = "\
+[2013-01-05 10:22] Running 'pacman -S vim'
+[2013-01-05 10:22] installed vim (7.3.754-1)
+[2023-01-05T10:22:31+0100] [PACMAN] Running 'pacman -S neovim htop'
+[2023-01-05T10:22:31+0100] [ALPM] transaction started
+[2023-01-05T10:22:31+0100] [ALPM] installed luajit (2.1.0-1)
+[2023-01-05T10:22:31+0100] [ALPM] installed neovim (0.8.2-1)
+[2023-01-05T10:22:32+0100] [ALPM] installed htop (3.2.1-1)
+[2023-01-05T10:22:32+0100] [ALPM-SCRIPTLET] installed this is not a package
+[2023-01-05T10:22:32+0100] [ALPM] transaction completed
+[2023-02-01T09:00:00+0100] [PACMAN] Running 'pacman -Syu'
+[2023-02-01T09:00:00+0100] [ALPM] transaction started
+[2023-02-01T09:00:00+0100] [ALPM] upgraded neovim (0.8.2-1 -> 0.8.3-1)
+[2023-02-01T09:00:00+0100] [ALPM] transaction completed
+[2023-03-01T09:00:00+0100] [PACMAN] Running 'pacman -Rs htop vim'
+[2023-03-01T09:00:00+0100] [ALPM] transaction started
+[2023-03-01T09:00:00+0100] [ALPM] removed htop (3.2.1-1)
+[2023-03-01T09:00:00+0100] [ALPM] removed vim (7.3.754-1)
+[2023-03-01T09:00:00+0100] [ALPM] transaction completed
+";
+
+    let transactions = parse_log(log);
+    assert_eq!(transactions.len(), 4);
+    assert_eq!(transactions[1].entries.len(), 3);
+
+    let explicit = HashSet::from(["neovim".to_string()]);
+    let steps = replay(transactions, &explicit);
+
+    let changes: Vec<(&str, Vec<String>, Vec<String>)> = steps
+        .iter()
+        .map(|step| {
+            (
+                step.timestamp.as_str(),
+                step.added.clone(),
+                step.removed.clone(),
+            )
+        })
+        .collect();
+
+    assert_eq!(
+        changes,
+        vec![
+            ("2013-01-05 10:22", vec!["vim".to_string()], vec![]),
+            (
+                "2023-01-05T10:22:31+0100",
+                vec!["neovim".to_string(), "htop".to_string()],
+                vec![]
+            ),
+            (
+                "2023-03-01T09:00:00+0100",
+                vec![],
+                vec!["htop".to_string(), "vim".to_string()]
+            ),
+        ]
+    );
+    assert_eq!(steps[2].packages, BTreeSet::from(["neovim".to_string()]));
+
+    let offset = |_: &str| Some("+01:00".to_string());
+    assert_eq!(
+        git_date("2013-01-05 10:22", offset),
+        "2013-01-05T10:22:00+01:00"
+    );
+    assert_eq!(
+        git_date("2023-01-05T10:22:31+0100", offset),
+        "2023-01-05T10:22:31+01:00"
+    );
+    assert_eq!(
+        git_date("2023-07-05T10:22:31-0430", offset),
+        "2023-07-05T10:22:31-04:30"
+    );
+}
@@ -13,6 +13,7 @@ pub fn install(args: Vec<String>) {
         Pacman | Paru | Yay => {
             pacman_compatible_proxy(
                 &config.package_manager,
+                &config.escalation,
                 &args,
                 vec!["-S", "--noconfirm"],
                 |proxied| {
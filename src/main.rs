@@ -1,17 +1,53 @@
+use std::path::PathBuf;
+
 use clap::{Parser, Subcommand};
 
-use crate::{generate::generate, install::install, remove::remove, sync::sync};
+use crate::{
+    config::set_config_dir,
+    configure::{config_edit, config_get, config_list, config_set, config_unset},
+    dotfiles::{adopt, diff, remove_package, restore_backup, status},
+    etc_files::{etc_add, etc_list, etc_remove},
+    generate::generate,
+    hook::{hook_install, hook_remove, hook_run},
+    import_log::import_log,
+    install::install,
+    output::{OutputFormat, emit_summary, set_output_format},
+    remove::remove,
+    secrets::{secret_add, secret_edit},
+    status::manifest_status,
+    sync::{DotfilesFilter, sync},
+};
 
+mod backups;
+mod checkout;
 mod commits;
 mod config;
+mod configure;
+mod copies;
+mod dotfiles;
 mod errors;
+mod etc_files;
 mod generate;
+mod hook;
+mod ignores;
+mod import_log;
 mod install;
 mod manifest;
+mod output;
+mod pacman_db;
+mod privileges;
 mod proxies;
 mod remove;
+mod repositories;
+mod secrets;
+mod services;
+mod state;
+mod status;
 mod successes;
+mod symlinks;
 mod sync;
+mod templates;
+mod validation;
 mod warnings;
 
 #[derive(Subcommand)]
@@ -32,11 +68,11 @@ enum Commands {
         args: Vec<String>,
     },
 
-    /// Generate a package manifest at ~/.config/metl/manifest.toml
+    /// Generate a package manifest at $XDG_CONFIG_HOME/metl/manifest.toml
     #[command(visible_alias = "g")]
     Generate,
 
-    /// Sync system with all packages in manifest file at ~/.config/metl/manifest.toml
+    /// Sync system with all packages in manifest file at $XDG_CONFIG_HOME/metl/manifest.toml
     #[command(visible_alias = "s")]
     Sync {
         /// Run a dry run without modifying the system
@@ -46,11 +82,229 @@ enum Commands {
         /// Enable verbose output
         #[arg(long, short = 'v')]
         verbose: bool,
+
+        /// Update the dotfiles checkout even if it has local modifications, discarding them
+        #[arg(long, short = 'f')]
+        force: bool,
+
+        /// Only apply these dotfile packages, comma separated, leaving packages, /etc files and services alone
+        #[arg(long, value_delimiter = ',', conflicts_with = "skip_dotfiles")]
+        dotfiles_only: Vec<String>,
+
+        /// Sync packages only, leaving the dotfiles untouched
+        #[arg(long)]
+        skip_dotfiles: bool,
+    },
+
+    /// Show manifest packages and services that drifted from the system
+    Status,
+
+    /// Reconstruct when explicit packages were added and removed from pacman.log
+    ImportLog {
+        /// pacman log to read
+        #[arg(long, default_value = "/var/log/pacman.log")]
+        log: PathBuf,
+
+        /// Replay the history as backdated commits in the manifest repo
+        #[arg(long, short = 'c')]
+        commit: bool,
+    },
+
+    /// Track edited /etc config files in the manifest repo, restored by sync
+    Etc {
+        #[command(subcommand)]
+        command: EtcCommands,
+    },
+
+    /// Keep the manifest up to date when pacman runs outside metl
+    Hook {
+        #[command(subcommand)]
+        command: HookCommands,
+    },
+
+    /// Read and edit metl settings
+    #[command(visible_alias = "c")]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+
+    /// Manage the dotfiles deployed by sync
+    #[command(visible_alias = "d")]
+    Dotfiles {
+        #[command(subcommand)]
+        command: DotfilesCommands,
+    },
+
+    /// Keep dotfiles encrypted with age, decrypted into place by sync
+    Secret {
+        #[command(subcommand)]
+        command: SecretCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Print the value of a config key
+    Get { key: String },
+
+    /// Set a config key, validated against the config schema
+    Set {
+        key: String,
+        value: String,
+
+        /// Do not commit and push the manifest repo afterwards
+        #[arg(long)]
+        no_commit: bool,
+    },
+
+    /// Remove a config key so its default applies
+    Unset {
+        key: String,
+
+        /// Do not commit and push the manifest repo afterwards
+        #[arg(long)]
+        no_commit: bool,
+    },
+
+    /// Print the full effective config
+    List,
+
+    /// Open the config in $VISUAL or $EDITOR, rejecting invalid edits
+    Edit {
+        /// Do not commit and push the manifest repo afterwards
+        #[arg(long)]
+        no_commit: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum EtcCommands {
+    /// Show package config files edited since install and the tracked ones
+    List,
+
+    /// Copy files from /etc into the manifest repo so sync restores them
+    Add {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Also track files other users can not read, keeping the copy private
+        #[arg(long)]
+        allow_private: bool,
+
+        /// Do not commit and push the manifest repo afterwards
+        #[arg(long)]
+        no_commit: bool,
+    },
+
+    /// Stop tracking files, leaving them in /etc
+    Remove {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Do not commit and push the manifest repo afterwards
+        #[arg(long)]
+        no_commit: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum HookCommands {
+    /// Write an ALPM hook that regenerates and commits the manifest after every transaction
+    Install,
+
+    /// Remove the ALPM hook
+    Remove,
+
+    /// Run by the ALPM hook with the transaction targets on stdin
+    #[command(hide = true)]
+    Run,
+}
+
+#[derive(Subcommand)]
+enum DotfilesCommands {
+    /// Move files from $HOME into a dotfiles package and link them back
+    Adopt {
+        /// Dotfiles package (top level directory) to adopt the files into
+        package: String,
+
+        /// Files or directories under $HOME to adopt
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Do not commit and push the dotfiles repo afterwards
+        #[arg(long)]
+        no_commit: bool,
+    },
+
+    /// Move files backed up during sync back into place
+    RestoreBackup {
+        /// Backup to restore, defaults to the most recent one
+        id: Option<String>,
+
+        /// List available backups instead of restoring
+        #[arg(long, short = 'l')]
+        list: bool,
+    },
+
+    /// Show whether each dotfile is linked, missing, conflicting or modified
+    Status {
+        /// Packages to check, defaults to the ones sync applies
+        packages: Vec<String>,
+    },
+
+    /// Show how copies in $HOME differ from the dotfiles repo
+    Diff {
+        /// Packages to diff, defaults to the ones sync applies
+        packages: Vec<String>,
+    },
+
+    /// Remove the links and copies sync made for a package, restoring backups
+    Remove {
+        package: String,
+
+        /// Show what would be removed without touching anything
+        #[arg(long, short = 'd')]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SecretCommands {
+    /// Encrypt a file from $HOME into a dotfiles package as <file>.age
+    Add {
+        /// Dotfiles package (top level directory) to add the secret to
+        package: String,
+
+        /// File under $HOME to encrypt
+        file: PathBuf,
+
+        /// Do not commit and push the dotfiles repo afterwards
+        #[arg(long)]
+        no_commit: bool,
+    },
+
+    /// Decrypt a secret, open it in $VISUAL or $EDITOR and encrypt it again
+    Edit {
+        /// The .age file, relative to the dotfiles repo or the current directory
+        file: PathBuf,
+
+        /// Do not commit and push the dotfiles repo afterwards
+        #[arg(long)]
+        no_commit: bool,
     },
 }
 
 #[derive(Parser)]
 struct Cli {
+    /// Use this directory for the metl config and manifest instead of $XDG_CONFIG_HOME/metl
+    #[arg(long, global = true, env = "METL_HOME")]
+    config_dir: Option<PathBuf>,
+
+    /// Output format, json emits one event object per line and a final summary
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
     #[command(subcommand)]
     commands: Commands,
 }
@@ -58,10 +312,78 @@ struct Cli {
 fn main() {
     let cli = Cli::parse();
 
+    set_output_format(cli.output);
+
+    if let Some(config_dir) = cli.config_dir {
+        set_config_dir(config_dir);
+    }
+
     match cli.commands {
         Commands::Install { args } => install(args),
         Commands::Remove { args } => remove(args),
         Commands::Generate => generate(),
-        Commands::Sync { dry_run, verbose } => sync(dry_run, verbose),
+        Commands::Sync {
+            dry_run,
+            verbose,
+            force,
+            dotfiles_only,
+            skip_dotfiles,
+        } => {
+            let dotfiles = match (skip_dotfiles, dotfiles_only.is_empty()) {
+                (true, _) => DotfilesFilter::Skip,
+                (false, true) => DotfilesFilter::Selected,
+                (false, false) => DotfilesFilter::Only(dotfiles_only),
+            };
+
+            sync(dry_run, verbose, force, dotfiles)
+        }
+        Commands::Status => manifest_status(),
+        Commands::ImportLog { log, commit } => import_log(log, commit),
+        Commands::Etc { command } => match command {
+            EtcCommands::List => etc_list(),
+            EtcCommands::Add {
+                files,
+                allow_private,
+                no_commit,
+            } => etc_add(files, allow_private, !no_commit),
+            EtcCommands::Remove { files, no_commit } => etc_remove(files, !no_commit),
+        },
+        Commands::Hook { command } => match command {
+            HookCommands::Install => hook_install(),
+            HookCommands::Remove => hook_remove(),
+            HookCommands::Run => hook_run(),
+        },
+        Commands::Config { command } => match command {
+            ConfigCommands::Get { key } => config_get(&key),
+            ConfigCommands::Set {
+                key,
+                value,
+                no_commit,
+            } => config_set(&key, &value, !no_commit),
+            ConfigCommands::Unset { key, no_commit } => config_unset(&key, !no_commit),
+            ConfigCommands::List => config_list(),
+            ConfigCommands::Edit { no_commit } => config_edit(!no_commit),
+        },
+        Commands::Dotfiles { command } => match command {
+            DotfilesCommands::Adopt {
+                package,
+                files,
+                no_commit,
+            } => adopt(package, files, !no_commit),
+            DotfilesCommands::RestoreBackup { id, list } => restore_backup(id, list),
+            DotfilesCommands::Remove { package, dry_run } => remove_package(package, dry_run),
+            DotfilesCommands::Status { packages } => status(packages),
+            DotfilesCommands::Diff { packages } => diff(packages),
+        },
+        Commands::Secret { command } => match command {
+            SecretCommands::Add {
+                package,
+                file,
+                no_commit,
+            } => secret_add(package, file, !no_commit),
+            SecretCommands::Edit { file, no_commit } => secret_edit(file, !no_commit),
+        },
     }
+
+    emit_summary();
 }
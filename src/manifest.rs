@@ -1,15 +1,129 @@
-use std::fs;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs,
+};
 
 use serde::{Deserialize, Serialize};
+use toml::value::Datetime;
 
 use crate::{
     config::get_config_path,
     errors::{failed_reading_manifest, manifest_parsing_error},
+    warnings::warn_unknown_group,
 };
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Default, Debug, Serialize, Deserialize)]
 pub struct Manifest {
+    /// Repositories `sync` adds to `pacman.conf` before installing packages
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub repositories: Vec<Repository>,
+
+    /// Package groups installed as a whole, like `base-devel`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<Group>,
+
     pub packages: Vec<Package>,
+
+    /// Units enabled on purpose, the ones presets enable are left out
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub services: Vec<Service>,
+}
+
+impl Manifest {
+    /// The manifest packages followed by the members of its groups that are
+    /// not listed already. A group missing from `groups` is warned about and
+    /// skipped, pacman reads the same sync databases and could not resolve
+    /// it either.
+    pub fn expanded_packages(&self, groups: &BTreeMap<String, BTreeSet<String>>) -> Vec<Package> {
+        let mut seen: HashSet<&str> = self
+            .packages
+            .iter()
+            .map(|package| package.name.as_str())
+            .collect();
+        let mut expanded = self.packages.clone();
+
+        for group in &self.groups {
+            let Some(members) = groups.get(&group.name) else {
+                warn_unknown_group(&group.name);
+                continue;
+            };
+
+            for member in members {
+                if seen.insert(member) {
+                    expanded.push(Package {
+                        name: member.to_string(),
+                        ..Package::default()
+                    });
+                }
+            }
+        }
+
+        expanded
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy, Hash)]
+pub enum ServiceScope {
+    #[default]
+    #[serde(rename(serialize = "system", deserialize = "system"))]
+    System,
+
+    #[serde(rename(serialize = "user", deserialize = "user"))]
+    User,
+}
+
+#[allow(clippy::to_string_trait_impl)]
+impl ToString for ServiceScope {
+    fn to_string(&self) -> String {
+        match self {
+            ServiceScope::System => "system".to_string(),
+            ServiceScope::User => "user".to_string(),
+        }
+    }
+}
+
+impl ServiceScope {
+    fn is_system(&self) -> bool {
+        *self == ServiceScope::System
+    }
+}
+
+/// A systemd unit `sync` enables and starts.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct Service {
+    pub name: String,
+
+    #[serde(default, skip_serializing_if = "ServiceScope::is_system")]
+    pub scope: ServiceScope,
+}
+
+/// A `pacman.conf` repository section and the keys its packages are
+/// signed with.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct Repository {
+    pub name: String,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub servers: Vec<String>,
+
+    /// A mirrorlist to include, like `/etc/pacman.d/chaotic-mirrorlist`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sig_level: Option<String>,
+
+    /// Fingerprints or IDs of keys to import and locally sign
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyserver: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct Group {
+    pub name: String,
 }
 
 #[derive(Default, Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
@@ -36,10 +150,21 @@ impl ToString for PackageManager {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Default, Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
 pub struct Package {
     pub name: String,
     pub version: Option<String>,
+
+    /// When pacman installed the package, kept across upgrades by `generate`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub installed_at: Option<Datetime>,
+
+    /// The sync repo the package came from, unset for foreign packages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub packager: Option<String>,
 }
 
 pub fn load_manifest() -> Manifest {
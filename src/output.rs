@@ -0,0 +1,382 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    panic,
+    process::Stdio,
+    sync::{Mutex, OnceLock},
+};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    ManifestGenerated {
+        manager: String,
+    },
+    PackageInstalled {
+        manager: String,
+        package: String,
+    },
+    PackageRemoved {
+        manager: String,
+        package: String,
+    },
+    PackageFailed {
+        manager: String,
+        package: String,
+        code: Option<i32>,
+        error: Option<String>,
+    },
+    PackagesSynced {
+        manager: String,
+        packages: Vec<String>,
+        failed: Vec<String>,
+    },
+    PackageStatus {
+        package: String,
+        status: String,
+        installed_at: Option<String>,
+        size: Option<u64>,
+        depends: Vec<String>,
+    },
+    PackagesInSync {
+        packages: usize,
+    },
+    ManifestHistory {
+        timestamp: String,
+        command: Option<String>,
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+    ManifestHistoryImported {
+        commits: usize,
+    },
+    EtcFileStatus {
+        path: String,
+        package: String,
+        status: String,
+    },
+    EtcFileTracked {
+        path: String,
+        tracked: bool,
+    },
+    EtcFileDiff {
+        path: String,
+        diff: String,
+    },
+    EtcFileRestored {
+        path: String,
+        dry_run: bool,
+    },
+    ServiceStatus {
+        service: String,
+        scope: String,
+        status: String,
+    },
+    ServicesInSync {
+        services: usize,
+    },
+    ServiceEnabled {
+        service: String,
+        scope: String,
+        dry_run: bool,
+    },
+    RepositoryKeyImported {
+        key: String,
+        dry_run: bool,
+    },
+    RepositoryAdded {
+        repository: String,
+        dry_run: bool,
+    },
+    RepositoriesRefreshed,
+    HookInstalled {
+        path: String,
+    },
+    HookRemoved {
+        path: String,
+        existed: bool,
+    },
+    DotfilesCloned {
+        repo: String,
+        path: String,
+        dry_run: bool,
+    },
+    DotfilesUpdated {
+        path: String,
+        dry_run: bool,
+    },
+    DotfileBackedUp {
+        target: String,
+        backup: String,
+        dry_run: bool,
+    },
+    DotfileRestored {
+        target: String,
+        backup: String,
+    },
+    DotfileAdopted {
+        original: String,
+        destination: String,
+    },
+    DotfileRemoved {
+        target: String,
+        restored: Option<String>,
+        dry_run: bool,
+    },
+    BackupListed {
+        id: String,
+        files: usize,
+    },
+    DotfileLinked {
+        name: String,
+        target: String,
+        source: String,
+        dry_run: bool,
+    },
+    DotfileCopied {
+        name: String,
+        target: String,
+        action: String,
+        dry_run: bool,
+    },
+    DotfileSkipped {
+        name: String,
+        reason: String,
+    },
+    SecretAdded {
+        original: String,
+        destination: String,
+    },
+    SecretEdited {
+        path: String,
+    },
+    DotfileStatus {
+        package: String,
+        target: String,
+        status: String,
+    },
+    DotfileDiff {
+        target: String,
+        source: String,
+        diff: String,
+    },
+    DotfileFailed {
+        name: String,
+        error: String,
+    },
+    CommitCreated {
+        repo: String,
+        message: String,
+    },
+    CommitPushed {
+        repo: String,
+    },
+    CommitFailed {
+        repo: String,
+        stage: String,
+        code: Option<i32>,
+        error: Option<String>,
+    },
+    ConfigValue {
+        key: String,
+        value: serde_json::Value,
+    },
+    ConfigChanged {
+        key: String,
+        value: Option<String>,
+    },
+    Warning {
+        message: String,
+    },
+    Error {
+        message: String,
+    },
+    Summary {
+        success: bool,
+        counts: BTreeMap<String, usize>,
+    },
+}
+
+static OUTPUT_FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+static EVENT_COUNTS: Mutex<BTreeMap<String, usize>> = Mutex::new(BTreeMap::new());
+
+/// Switches the process to `format`. In JSON mode colors are disabled and a
+/// panic from `errors.rs` becomes an `error` event followed by the summary.
+pub fn set_output_format(format: OutputFormat) {
+    let _ = OUTPUT_FORMAT.set(format);
+
+    if format == OutputFormat::Json {
+        colored::control::set_override(false);
+
+        panic::set_hook(Box::new(|info| {
+            let payload = info.payload();
+            let message = payload
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| payload.downcast_ref::<&str>().map(|m| m.to_string()))
+                .unwrap_or_else(|| "metl exited unexpectedly".to_string());
+
+            emit(Event::Error { message });
+            emit_summary();
+        }));
+    }
+}
+
+pub fn is_json() -> bool {
+    OUTPUT_FORMAT.get() == Some(&OutputFormat::Json)
+}
+
+/// Prints `event` as a single JSON line and counts it for the summary.
+pub fn emit(event: Event) {
+    let Ok(line) = serde_json::to_value(&event) else {
+        return;
+    };
+
+    if let Some(name) = line.get("event").and_then(|name| name.as_str())
+        && let Ok(mut counts) = EVENT_COUNTS.lock()
+    {
+        *counts.entry(name.to_string()).or_default() += 1;
+    }
+
+    println!("{line}");
+}
+
+pub fn emit_summary() {
+    if !is_json() {
+        return;
+    }
+
+    let counts = EVENT_COUNTS
+        .lock()
+        .map(|counts| counts.clone())
+        .unwrap_or_default();
+
+    emit(summary(counts));
+}
+
+/// A run failed when any error or `*_failed` event was emitted.
+fn summary(counts: BTreeMap<String, usize>) -> Event {
+    let success = !counts
+        .keys()
+        .any(|name| name == "error" || name.ends_with("_failed"));
+
+    Event::Summary { success, counts }
+}
+
+/// Child process output would corrupt the JSON stream, so it goes to stderr.
+pub fn child_stdout() -> Stdio {
+    child_stdout_for(OUTPUT_FORMAT.get().copied().unwrap_or_default())
+}
+
+fn child_stdout_for(format: OutputFormat) -> Stdio {
+    match format {
+        OutputFormat::Json => Stdio::from(io::stderr()),
+        OutputFormat::Text => Stdio::inherit(),
+    }
+}
+
+/// Free-form diagnostic text (verbose logs, tool output) that is not an event.
+pub fn print_detail(text: &str) {
+    match is_json() {
+        true => eprintln!("{text}"),
+        false => println!("{text}"),
+    }
+}
+
+/// Asks a yes/no question on the terminal, defaulting to no.
+pub fn prompt_confirm(question: &str) -> bool {
+    match is_json() {
+        true => eprint!("{question} [y/N] "),
+        false => print!("{question} [y/N] "),
+    }
+
+    let _ = io::stdout().flush();
+
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+#[test]
+fn test_event_encoding() {
+    let failed = Event::PackageFailed {
+        manager: "pacman".to_string(),
+        package: "neovim".to_string(),
+        code: Some(1),
+        error: None,
+    };
+
+    assert_eq!(
+        serde_json::to_value(&failed).unwrap(),
+        serde_json::json!({
+            "event": "package_failed",
+            "manager": "pacman",
+            "package": "neovim",
+            "code": 1,
+            "error": null,
+        })
+    );
+    assert_eq!(
+        serde_json::to_string(&Event::RepositoriesRefreshed).unwrap(),
+        r#"{"event":"repositories_refreshed"}"#
+    );
+
+    let counts = |names: &[&str]| -> BTreeMap<String, usize> {
+        names.iter().map(|name| (name.to_string(), 1)).collect()
+    };
+
+    assert_eq!(
+        serde_json::to_value(summary(counts(&["package_installed", "commit_pushed"]))).unwrap(),
+        serde_json::json!({
+            "event": "summary",
+            "success": true,
+            "counts": { "commit_pushed": 1, "package_installed": 1 },
+        })
+    );
+    assert!(matches!(
+        summary(counts(&["package_installed", "commit_failed"])),
+        Event::Summary { success: false, .. }
+    ));
+    assert!(matches!(
+        summary(counts(&["error"])),
+        Event::Summary { success: false, .. }
+    ));
+}
+
+#[test]
+fn test_child_stdout_goes_to_stderr() {
+    use std::{env, process::Command};
+
+    // NOTE: reruns this test in a child so its stdout and stderr can be read
+    if env::var_os("METL_TEST_CHILD_STDOUT").is_some() {
+        Command::new("echo")
+            .arg("from the child")
+            .stdout(child_stdout_for(OutputFormat::Json))
+            .status()
+            .unwrap();
+        return;
+    }
+
+    let output = Command::new(env::current_exe().unwrap())
+        .args(["--exact", "output::test_child_stdout_goes_to_stderr"])
+        .env("METL_TEST_CHILD_STDOUT", "1")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("from the child"));
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("from the child"));
+}
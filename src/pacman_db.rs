@@ -0,0 +1,528 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+use thiserror::Error;
+use toml::value::{Date, Datetime, Offset, Time};
+
+use crate::warnings::warn_sync_db_unreadable;
+
+pub const PACMAN_DB_PATH: &str = "/var/lib/pacman";
+pub const PACMAN_CONF_PATH: &str = "/etc/pacman.conf";
+
+#[derive(Debug, Error)]
+pub enum PacmanDbError {
+    #[error("could not read {path}: {source}")]
+    Read { path: PathBuf, source: io::Error },
+
+    #[error("{path} has no %{field}% entry")]
+    MissingField { path: PathBuf, field: &'static str },
+
+    #[error("{path} has an invalid %{field}% entry: {value}")]
+    InvalidField {
+        path: PathBuf,
+        field: &'static str,
+        value: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallReason {
+    Explicit,
+    Dependency,
+}
+
+/// A package as recorded in pacman's local database.
+#[derive(Debug, PartialEq, Eq)]
+pub struct InstalledPackage {
+    pub name: String,
+    pub version: String,
+    pub reason: InstallReason,
+
+    /// Unix timestamp of the install
+    pub install_date: Option<i64>,
+
+    /// The sync repo the package is available from, `None` for foreign
+    /// packages like the ones built from the AUR
+    pub repository: Option<String>,
+
+    pub packager: Option<String>,
+
+    /// Installed size in bytes
+    pub size: u64,
+
+    /// Dependencies as pacman lists them, version constraints included
+    pub depends: Vec<String>,
+}
+
+impl InstalledPackage {
+    pub fn installed_at(&self) -> Option<Datetime> {
+        self.install_date.map(utc_datetime)
+    }
+}
+
+/// Converts a unix timestamp to a UTC datetime, using the days to civil date
+/// algorithm from Howard Hinnant's date library.
+fn utc_datetime(timestamp: i64) -> Datetime {
+    let days = timestamp.div_euclid(86400);
+    let seconds = timestamp.rem_euclid(86400);
+
+    let shifted = days + 719468;
+    let era = shifted.div_euclid(146097);
+    let day_of_era = shifted.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = match shifted_month {
+        month if month < 10 => month + 3,
+        month => month - 9,
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    Datetime {
+        date: Some(Date {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+        }),
+        time: Some(Time {
+            hour: (seconds / 3600) as u8,
+            minute: (seconds % 3600 / 60) as u8,
+            second: (seconds % 60) as u8,
+            nanosecond: 0,
+        }),
+        offset: Some(Offset::Z),
+    }
+}
+
+/// What the sync databases in `db_path/sync` say about the packages
+/// available. Gzip compressed and plain databases are read, the others are
+/// warned about and skipped.
+#[derive(Default, Debug)]
+pub struct SyncDatabases {
+    /// The first repo providing each package, in `pacman.conf` order so a
+    /// package in several repos resolves the way pacman would
+    pub repositories: HashMap<String, String>,
+
+    /// Every member of each package group, across all repos
+    pub groups: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl SyncDatabases {
+    pub fn load(db_path: &Path) -> Self {
+        let sync_path = db_path.join("sync");
+        let mut databases = SyncDatabases::default();
+
+        for repository in repository_order(&sync_path) {
+            let path = sync_path.join(format!("{repository}.db"));
+
+            // NOTE: a repo not synced yet has no database to warn about
+            let packages = match sync_db_packages(&path) {
+                Ok(packages) => packages,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                Err(error) => {
+                    warn_sync_db_unreadable(&path, error);
+                    continue;
+                }
+            };
+
+            for (name, groups) in packages {
+                for group in groups {
+                    databases
+                        .groups
+                        .entry(group)
+                        .or_default()
+                        .insert(name.clone());
+                }
+
+                databases
+                    .repositories
+                    .entry(name)
+                    .or_insert_with(|| repository.clone());
+            }
+        }
+
+        databases
+    }
+}
+
+/// Reads every package in `db_path/local`, sorted by name, with the
+/// repository each one is available from according to `sync`.
+pub fn installed_packages(
+    db_path: &Path,
+    sync: &SyncDatabases,
+) -> Result<Vec<InstalledPackage>, PacmanDbError> {
+    let local_path = db_path.join("local");
+    let read_error = |path: &Path, source| PacmanDbError::Read {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    let entries = fs::read_dir(&local_path).map_err(|error| read_error(&local_path, error))?;
+
+    let mut packages = vec![];
+    for entry in entries.flatten() {
+        let desc_path = entry.path().join("desc");
+        if !desc_path.is_file() {
+            continue;
+        }
+
+        let desc = fs::read_to_string(&desc_path).map_err(|error| read_error(&desc_path, error))?;
+        let mut package = parse_desc(&desc, &desc_path)?;
+        package.repository = sync.repositories.get(&package.name).cloned();
+
+        packages.push(package);
+    }
+
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(packages)
+}
+
+/// A config file a package marks for backup, which pacman leaves alone on
+/// upgrades when it was edited.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BackupFile {
+    pub package: String,
+    pub path: PathBuf,
+
+    /// MD5 of the file as the package shipped it
+    pub md5: String,
+}
+
+impl BackupFile {
+    pub fn is_modified(&self, contents: &[u8]) -> bool {
+        format!("{:x}", md5::compute(contents)) != self.md5
+    }
+}
+
+/// Reads the backup files of every package in `db_path/local`, from the
+/// `%BACKUP%` section of each package's `files`.
+pub fn backup_files(db_path: &Path) -> Result<Vec<BackupFile>, PacmanDbError> {
+    let local_path = db_path.join("local");
+    let read_error = |path: &Path, source| PacmanDbError::Read {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    let entries = fs::read_dir(&local_path).map_err(|error| read_error(&local_path, error))?;
+
+    let mut backups = vec![];
+    for entry in entries.flatten() {
+        let files_path = entry.path().join("files");
+        if !files_path.is_file() {
+            continue;
+        }
+
+        // NOTE: neither pkgver nor pkgrel may contain a dash
+        let dir = entry.file_name().to_string_lossy().to_string();
+        let Some(package) = dir.rsplitn(3, '-').nth(2) else {
+            continue;
+        };
+
+        let files =
+            fs::read_to_string(&files_path).map_err(|error| read_error(&files_path, error))?;
+        backups.extend(parse_backups(&files, package));
+    }
+
+    backups.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(backups)
+}
+
+fn parse_backups(files: &str, package: &str) -> Vec<BackupFile> {
+    desc_fields(files)
+        .get("BACKUP")
+        .into_iter()
+        .flatten()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(path, md5)| BackupFile {
+            package: package.to_string(),
+            path: Path::new("/").join(path),
+            md5: md5.to_string(),
+        })
+        .collect()
+}
+
+/// Splits a `desc` file into its `%FIELD%` sections, each a list of lines.
+fn desc_fields(desc: &str) -> HashMap<&str, Vec<&str>> {
+    let mut fields: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut current = None;
+
+    for line in desc.lines() {
+        if let Some(field) = line
+            .strip_prefix('%')
+            .and_then(|line| line.strip_suffix('%'))
+        {
+            current = Some(field);
+            fields.entry(field).or_default();
+            continue;
+        }
+
+        match (current, line.is_empty()) {
+            (Some(field), false) => fields.entry(field).or_default().push(line),
+            _ => current = None,
+        }
+    }
+
+    fields
+}
+
+fn parse_desc(desc: &str, path: &Path) -> Result<InstalledPackage, PacmanDbError> {
+    let fields = desc_fields(desc);
+    let first = |field: &str| fields.get(field).and_then(|values| values.first().copied());
+
+    let required = |field: &'static str| {
+        first(field).ok_or_else(|| PacmanDbError::MissingField {
+            path: path.to_path_buf(),
+            field,
+        })
+    };
+
+    let number = |field: &'static str| -> Result<Option<i64>, PacmanDbError> {
+        first(field)
+            .map(|value| {
+                value
+                    .parse::<i64>()
+                    .map_err(|_| PacmanDbError::InvalidField {
+                        path: path.to_path_buf(),
+                        field,
+                        value: value.to_string(),
+                    })
+            })
+            .transpose()
+    };
+
+    // NOTE: pacman only writes %REASON% for dependencies, as 1
+    let reason = match number("REASON")? {
+        Some(1) => InstallReason::Dependency,
+        _ => InstallReason::Explicit,
+    };
+
+    Ok(InstalledPackage {
+        name: required("NAME")?.to_string(),
+        version: required("VERSION")?.to_string(),
+        reason,
+        install_date: number("INSTALLDATE")?,
+        repository: None,
+        packager: first("PACKAGER").map(String::from),
+        size: number("SIZE")?.unwrap_or_default().max(0) as u64,
+        depends: fields
+            .get("DEPENDS")
+            .map(|depends| depends.iter().map(|depend| depend.to_string()).collect())
+            .unwrap_or_default(),
+    })
+}
+
+/// The repo sections in `pacman_conf`, in order.
+pub fn configured_repositories(pacman_conf: &str) -> Vec<String> {
+    pacman_conf
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix('[')?.strip_suffix(']'))
+        .filter(|section| *section != "options")
+        .map(String::from)
+        .collect()
+}
+
+/// The repos in `pacman.conf`, falling back to the sync databases on disk
+/// sorted by name.
+fn repository_order(sync_path: &Path) -> Vec<String> {
+    let configured =
+        configured_repositories(&fs::read_to_string(PACMAN_CONF_PATH).unwrap_or_default());
+
+    if !configured.is_empty() {
+        return configured;
+    }
+
+    let mut on_disk: Vec<String> = fs::read_dir(sync_path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            name.strip_suffix(".db").map(String::from)
+        })
+        .collect();
+    on_disk.sort();
+
+    on_disk
+}
+
+/// The packages in a sync database with their groups, read from the
+/// `name-pkgver-pkgrel/desc` entry of each package.
+fn sync_db_packages(db_path: &Path) -> io::Result<Vec<(String, Vec<String>)>> {
+    let archive = unpack_sync_db(fs::read(db_path)?)?;
+
+    let mut packages = vec![];
+    for entry in tar::Archive::new(archive.as_slice()).entries()? {
+        let mut entry = entry?;
+        if entry.path()?.file_name().is_none_or(|name| name != "desc") {
+            continue;
+        }
+
+        let mut desc = String::new();
+        entry.read_to_string(&mut desc)?;
+
+        let fields = desc_fields(&desc);
+        let Some(name) = fields.get("NAME").and_then(|names| names.first()) else {
+            continue;
+        };
+
+        let groups = fields
+            .get("GROUPS")
+            .map(|groups| groups.iter().map(|group| group.to_string()).collect())
+            .unwrap_or_default();
+
+        packages.push((name.to_string(), groups));
+    }
+
+    Ok(packages)
+}
+
+/// The tar archive in a sync database, which `repo-add` writes gzip
+/// compressed by default and uncompressed or zstd compressed on request.
+fn unpack_sync_db(data: Vec<u8>) -> io::Result<Vec<u8>> {
+    const TAR_MAGIC: &[u8] = b"ustar";
+    const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+    match data.as_slice() {
+        [0x1f, 0x8b, ..] => {
+            let mut archive = vec![];
+            GzDecoder::new(data.as_slice()).read_to_end(&mut archive)?;
+            Ok(archive)
+        }
+        _ if data.get(257..262) == Some(TAR_MAGIC) => Ok(data),
+        _ if data.starts_with(&ZSTD_MAGIC) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "zstd compressed sync databases are not supported",
+        )),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a gzip compressed or plain tar archive",
+        )),
+    }
+}
+
+#[test]
+fn test_parse_desc() {
+    let desc = "%NAME%
+neovim
+
+%VERSION%
+0.11.4-1
+
+%DESC%
+Fork of Vim aiming to improve user experience, plugins, and GUIs
+
+%INSTALLDATE%
+1758010000
+
+%PACKAGER%
+Jane Doe <jane@archlinux.org>
+
+%SIZE%
+27635419
+
+%DEPENDS%
+libluv
+libutf8proc
+luajit
+
+";
+
+    let Ok(package) = parse_desc(desc, Path::new("neovim/desc")) else {
+        panic!("Expected the desc to parse");
+    };
+
+    assert_eq!(
+        package,
+        InstalledPackage {
+            name: "neovim".into(),
+            version: "0.11.4-1".into(),
+            reason: InstallReason::Explicit,
+            install_date: Some(1758010000),
+            repository: None,
+            packager: Some("Jane Doe <jane@archlinux.org>".into()),
+            size: 27635419,
+            depends: vec!["libluv".into(), "libutf8proc".into(), "luajit".into()],
+        }
+    );
+
+    assert_eq!(
+        package
+            .installed_at()
+            .map(|installed_at| installed_at.to_string()),
+        Some("2025-09-16T08:06:40Z".to_string())
+    );
+
+    let dependency = format!("{desc}%REASON%\n1\n\n");
+    assert!(matches!(
+        parse_desc(&dependency, Path::new("neovim/desc")),
+        Ok(InstalledPackage {
+            reason: InstallReason::Dependency,
+            ..
+        })
+    ));
+
+    assert!(matches!(
+        parse_desc("%VERSION%\n1.0-1\n", Path::new("broken/desc")),
+        Err(PacmanDbError::MissingField { field: "NAME", .. })
+    ));
+
+    let files = "%FILES%
+etc/
+etc/pacman.conf
+
+%BACKUP%
+etc/pacman.conf\t7f427487a0a9a6fe8387d0b714cb9bd1
+";
+
+    let backups = parse_backups(files, "pacman");
+    assert_eq!(backups.len(), 1);
+    assert_eq!(backups[0].path, Path::new("/etc/pacman.conf"));
+    assert!(!backups[0].is_modified(b"[options]\n"));
+    assert!(backups[0].is_modified(b"[options]\nColor\n"));
+
+    let root = std::env::temp_dir().join(format!("metl-pacman-db-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+
+    let desc = b"%NAME%\ngcc\n\n%GROUPS%\nbase-devel\n\n";
+    let mut header = tar::Header::new_ustar();
+    header.set_size(desc.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+
+    let mut builder = tar::Builder::new(vec![]);
+    builder
+        .append_data(&mut header, "gcc-15.2.1-1/desc", &desc[..])
+        .unwrap();
+    let archive = builder.into_inner().unwrap();
+
+    let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    std::io::Write::write_all(&mut encoder, &archive).unwrap();
+
+    fs::write(root.join("core.db"), encoder.finish().unwrap()).unwrap();
+    fs::write(root.join("custom.db"), &archive).unwrap();
+    fs::write(root.join("extra.db"), [0x28, 0xb5, 0x2f, 0xfd, 0, 0]).unwrap();
+
+    for db in ["core.db", "custom.db"] {
+        assert_eq!(
+            sync_db_packages(&root.join(db)).unwrap(),
+            vec![("gcc".to_string(), vec!["base-devel".to_string()])]
+        );
+    }
+    assert_eq!(
+        sync_db_packages(&root.join("extra.db")).unwrap_err().kind(),
+        io::ErrorKind::Unsupported
+    );
+
+    let _ = fs::remove_dir_all(&root);
+}
@@ -0,0 +1,143 @@
+use std::{
+    env,
+    fs::{self, DirBuilder},
+    io,
+    os::unix::fs::{DirBuilderExt, MetadataExt},
+    path::{Path, PathBuf},
+    process::{self, Command},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    manifest::PackageManager::{self, Pacman, Paru, Yay},
+    output::child_stdout,
+};
+
+#[derive(Default, Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub enum Escalation {
+    #[default]
+    #[serde(rename(serialize = "sudo", deserialize = "sudo"))]
+    Sudo,
+
+    #[serde(rename(serialize = "doas", deserialize = "doas"))]
+    Doas,
+
+    #[serde(rename(serialize = "run0", deserialize = "run0"))]
+    Run0,
+
+    #[serde(rename(serialize = "none", deserialize = "none"))]
+    None,
+}
+
+#[allow(clippy::to_string_trait_impl)]
+impl ToString for Escalation {
+    fn to_string(&self) -> String {
+        match self {
+            Escalation::Sudo => "sudo".to_string(),
+            Escalation::Doas => "doas".to_string(),
+            Escalation::Run0 => "run0".to_string(),
+            Escalation::None => "none".to_string(),
+        }
+    }
+}
+
+pub fn is_root() -> bool {
+    fs::metadata("/proc/self")
+        .map(|metadata| metadata.uid() == 0)
+        .unwrap_or(false)
+}
+
+/// Returns the escalation binary needed to run `manager`, if any.
+///
+/// AUR helpers refuse to run as root and call the escalation tool
+/// themselves, so only pacman is ever wrapped.
+pub fn escalation_tool(manager: &PackageManager, escalation: &Escalation) -> Option<String> {
+    match (manager, escalation) {
+        (_, Escalation::None) => None,
+        (Paru | Yay, _) => None,
+        (Pacman, _) if is_root() => None,
+        (Pacman, escalation) => Some(escalation.to_string()),
+    }
+}
+
+pub fn package_manager_command(manager: &PackageManager, escalation: &Escalation) -> Command {
+    match escalation_tool(manager, escalation) {
+        Some(tool) => {
+            let mut command = Command::new(tool);
+            command.arg(manager.to_string());
+            command
+        }
+
+        None => Command::new(manager.to_string()),
+    }
+}
+
+/// Runs `program` through the configured escalation tool unless metl is
+/// already root.
+pub fn escalated_command(program: &str, escalation: &Escalation) -> Command {
+    match escalation {
+        Escalation::None => Command::new(program),
+        _ if is_root() => Command::new(program),
+        escalation => {
+            let mut command = Command::new(escalation.to_string());
+            command.arg(program);
+            command
+        }
+    }
+}
+
+/// A temp directory only the current user can read, removed again on drop
+/// so files staged in it, like decrypted secrets or files root installs into
+/// `/etc`, are never exposed to other users and never outlive the command.
+pub struct PrivateDir(PathBuf);
+
+impl PrivateDir {
+    pub fn new(purpose: &str) -> io::Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.subsec_nanos())
+            .unwrap_or_default();
+
+        // NOTE: create fails on an existing path, so nobody can plant it
+        let path = env::temp_dir().join(format!("metl-{purpose}-{}-{nanos}", process::id()));
+        DirBuilder::new().mode(0o700).create(&path)?;
+
+        Ok(PrivateDir(path))
+    }
+
+    pub fn join(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for PrivateDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Runs `command` to completion, failing when it exits unsuccessfully. Its
+/// stdout goes where `child_stdout` says, so it never mixes into JSON output.
+pub fn run_escalated(command: &mut Command) -> io::Result<()> {
+    let status = command.stdout(child_stdout()).status()?;
+
+    match status.success() {
+        true => Ok(()),
+        false => Err(io::Error::other(format!(
+            "{:?} exited with {status}",
+            command.get_program()
+        ))),
+    }
+}
+
+#[test]
+fn test_aur_helpers_are_not_escalated() {
+    let command = package_manager_command(&PackageManager::Paru, &Escalation::Sudo);
+    assert_eq!(command.get_program(), "paru");
+    assert_eq!(command.get_args().count(), 0);
+
+    let command = package_manager_command(&PackageManager::Pacman, &Escalation::None);
+    assert_eq!(command.get_program(), "pacman");
+}
@@ -1,14 +1,18 @@
+use std::io::Write;
 use std::process::Stdio;
-use std::{io::Write, process::Command};
 
 use crate::commits::commit_manifest;
 use crate::errors::unsupported_package_manager;
+use crate::hook::ProxiedTransaction;
 use crate::{errors::package_install_failed, generate::generate, manifest::PackageManager};
 
 use crate::manifest::PackageManager::{Pacman, Paru, Yay};
+use crate::output::child_stdout;
+use crate::privileges::{Escalation, package_manager_command};
 
 pub fn pacman_compatible_proxy<S, F>(
     manager: &PackageManager,
+    escalation: &Escalation,
     args: &[String],
     default_args: Vec<&str>,
     success: S,
@@ -17,11 +21,9 @@ pub fn pacman_compatible_proxy<S, F>(
     S: Fn(&str),
     F: Fn(&str, i32),
 {
-    let mut command = Command::new("sudo");
-
     #[allow(unreachable_patterns)]
-    match manager {
-        Yay | Paru | Pacman => command.arg(manager.to_string()),
+    let mut command = match manager {
+        Yay | Paru | Pacman => package_manager_command(manager, escalation),
         _ => unsupported_package_manager(manager),
     };
 
@@ -48,14 +50,17 @@ pub fn pacman_compatible_proxy<S, F>(
     proxied_cmd = proxied_cmd.trim().to_string();
 
     command.stdin(Stdio::inherit());
-    command.stdout(Stdio::inherit());
+    command.stdout(child_stdout());
     command.stderr(Stdio::inherit());
 
+    let transaction = ProxiedTransaction::start();
     let output = match command.output() {
         Ok(output) => output,
         Err(error) => package_install_failed(&proxied_cmd, error),
     };
 
+    drop(transaction);
+
     let code = output.status.code().unwrap_or(1);
     let verbose = has_verbose(args);
 
@@ -13,6 +13,7 @@ pub fn remove(args: Vec<String>) {
         Pacman | Paru | Yay => {
             pacman_compatible_proxy(
                 &config.package_manager,
+                &config.escalation,
                 &args,
                 vec!["-R", "--noconfirm"],
                 |proxied| {
@@ -0,0 +1,200 @@
+use std::{fs, io, path::Path, process::Stdio};
+
+use crate::{
+    config::Config,
+    etc_files::install_system_file,
+    manifest::{Manifest, Repository},
+    output::print_detail,
+    pacman_db::{PACMAN_CONF_PATH, configured_repositories},
+    privileges::{Escalation, PrivateDir, escalated_command, run_escalated},
+    successes::{repository_added, repository_key_imported},
+    warnings::warn_repository_failed,
+};
+
+/// The `pacman.conf` section for `repository`, preceded by a blank line.
+fn render_section(repository: &Repository) -> String {
+    let mut section = format!("\n[{}]\n", repository.name);
+
+    if let Some(sig_level) = &repository.sig_level {
+        section.push_str(&format!("SigLevel = {sig_level}\n"));
+    }
+
+    for server in &repository.servers {
+        section.push_str(&format!("Server = {server}\n"));
+    }
+
+    if let Some(include) = &repository.include {
+        section.push_str(&format!("Include = {include}\n"));
+    }
+
+    section
+}
+
+/// The declared repositories `pacman_conf` has no section for, leaving out
+/// the ones pacman could not use.
+fn missing_repositories<'a>(
+    repositories: &'a [Repository],
+    pacman_conf: &str,
+) -> Vec<&'a Repository> {
+    let configured = configured_repositories(pacman_conf);
+
+    repositories
+        .iter()
+        .filter(|repository| !configured.contains(&repository.name))
+        .filter(|repository| {
+            let reason = match repository {
+                Repository { name, .. } if name.is_empty() || name == "options" => {
+                    "is not a valid repository name"
+                }
+                Repository {
+                    servers,
+                    include: None,
+                    ..
+                } if servers.is_empty() => "has no servers or include",
+                _ => return true,
+            };
+
+            warn_repository_failed(&repository.name, reason);
+            false
+        })
+        .collect()
+}
+
+fn is_key_known(key: &str, escalation: &Escalation) -> bool {
+    escalated_command("pacman-key", escalation)
+        .arg("--list-keys")
+        .arg(key)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Receives `key` into pacman's keyring and signs it locally so packages
+/// signed with it are trusted.
+fn import_key(key: &str, keyserver: Option<&str>, escalation: &Escalation) -> io::Result<()> {
+    let mut receive = escalated_command("pacman-key", escalation);
+    if let Some(keyserver) = keyserver {
+        receive.arg("--keyserver").arg(keyserver);
+    }
+
+    run_escalated(receive.arg("--recv-keys").arg(key))?;
+    run_escalated(
+        escalated_command("pacman-key", escalation)
+            .arg("--lsign-key")
+            .arg(key),
+    )
+}
+
+/// Imports the keys and adds the `pacman.conf` sections of the manifest
+/// repositories that are missing. Returns whether a repository was added, so
+/// the package databases need a refresh before its packages can be installed.
+pub fn prepare_repositories(config: &Config, manifest: &Manifest, dry_run: bool) -> bool {
+    let escalation = &config.escalation;
+
+    for repository in &manifest.repositories {
+        for key in &repository.keys {
+            if is_key_known(key, escalation) {
+                continue;
+            }
+
+            if dry_run {
+                repository_key_imported(key, true);
+                continue;
+            }
+
+            match import_key(key, repository.keyserver.as_deref(), escalation) {
+                Ok(_) => repository_key_imported(key, false),
+                Err(error) => warn_repository_failed(
+                    &repository.name,
+                    &format!("could not import key {key}: {error}"),
+                ),
+            }
+        }
+    }
+
+    if manifest.repositories.is_empty() {
+        return false;
+    }
+
+    let pacman_conf = match fs::read_to_string(PACMAN_CONF_PATH) {
+        Ok(pacman_conf) => pacman_conf,
+        Err(error) => {
+            warn_repository_failed(PACMAN_CONF_PATH, &error.to_string());
+            return false;
+        }
+    };
+
+    let missing = missing_repositories(&manifest.repositories, &pacman_conf);
+    if missing.is_empty() {
+        return false;
+    }
+
+    let sections: String = missing
+        .iter()
+        .map(|repository| render_section(repository))
+        .collect();
+
+    if dry_run {
+        missing
+            .iter()
+            .for_each(|repository| repository_added(&repository.name, true));
+
+        print_detail(sections.trim_start());
+        return false;
+    }
+
+    let written = PrivateDir::new("pacman-conf").and_then(|private_dir| {
+        let staged = private_dir.join("pacman.conf");
+        fs::write(&staged, format!("{}\n{sections}", pacman_conf.trim_end()))?;
+
+        install_system_file(&staged, Path::new(PACMAN_CONF_PATH), escalation)
+    });
+
+    if let Err(error) = written {
+        missing
+            .iter()
+            .for_each(|repository| warn_repository_failed(&repository.name, &error.to_string()));
+        return false;
+    }
+
+    missing
+        .iter()
+        .for_each(|repository| repository_added(&repository.name, false));
+
+    true
+}
+
+#[test]
+fn test_missing_repositories() {
+    let repository = |name: &str, servers: &[&str]| Repository {
+        name: name.to_string(),
+        servers: servers.iter().map(|server| server.to_string()).collect(),
+        include: None,
+        sig_level: None,
+        keys: vec![],
+        keyserver: None,
+    };
+
+    let mut chaotic = repository("chaotic-aur", &[]);
+    chaotic.include = Some("/etc/pacman.d/chaotic-mirrorlist".to_string());
+
+    let repositories = vec![
+        repository("core", &["https://mirror.example/$repo/os/$arch"]),
+        chaotic,
+        repository("broken", &[]),
+    ];
+    let pacman_conf =
+        "[options]\nHoldPkg = pacman glibc\n\n[core]\nInclude = /etc/pacman.d/mirrorlist\n";
+
+    let missing = missing_repositories(&repositories, pacman_conf);
+    assert_eq!(missing, vec![&repositories[1]]);
+
+    let mut custom = repository("custom", &["file:///srv/repo"]);
+    custom.sig_level = Some("Optional TrustAll".to_string());
+
+    assert_eq!(
+        render_section(&custom),
+        "\n[custom]\nSigLevel = Optional TrustAll\nServer = file:///srv/repo\n"
+    );
+}
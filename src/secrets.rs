@@ -0,0 +1,270 @@
+use std::{
+    env, fs, io, mem,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use crate::{
+    commits::commit_dotfiles,
+    config::{get_home_path, load_config},
+    configure::preferred_editor,
+    copies::write_restricted,
+    errors::{
+        editor_failed, invalid_secret_file, missing_dotfiles_checkout, missing_secrets_identity,
+        secret_edit_not_saved, secret_failed,
+    },
+    privileges::PrivateDir,
+    state::{DeployedKind, DotfilesState},
+    successes::{dotfile_file_copied, secret_added, secret_edited},
+    symlinks::normalize,
+    warnings::{warn_dotfiles_state_not_saved, warn_secret_copy_stale},
+};
+
+pub fn is_secret(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "age")
+}
+
+fn run_age(command: &mut Command) -> io::Result<Output> {
+    let output = command
+        .output()
+        .map_err(|error| io::Error::new(error.kind(), format!("could not run age: {error}")))?;
+
+    if !output.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    Ok(output)
+}
+
+pub fn decrypt(source: &Path, identity: &Path) -> io::Result<Vec<u8>> {
+    let output = run_age(
+        Command::new("age")
+            .arg("--decrypt")
+            .arg("--identity")
+            .arg(identity)
+            .arg(source),
+    )?;
+
+    Ok(output.stdout)
+}
+
+/// Encrypts `plaintext` to the recipient of `identity`, replacing `output`
+/// only once age succeeded.
+pub fn encrypt(plaintext: &Path, output: &Path, identity: &Path) -> io::Result<()> {
+    let pending = PathBuf::from(format!("{}.new", output.to_string_lossy()));
+
+    run_age(
+        Command::new("age")
+            .args(["--encrypt", "--armor", "--identity"])
+            .arg(identity)
+            .arg("--output")
+            .arg(&pending)
+            .arg(plaintext),
+    )
+    .inspect_err(|_| {
+        let _ = fs::remove_file(&pending);
+    })?;
+
+    fs::rename(&pending, output)
+}
+
+/// Encrypts `file` from `$HOME` into `package` as `<file>.age`, leaving the
+/// plaintext in place as the deployed copy.
+pub fn secret_add(package: String, file: PathBuf, commit: bool) {
+    let config = load_config();
+    let home_dir = get_home_path();
+    let dotfiles_path = config.dotfiles_path();
+    let identity = config.secrets_identity_path();
+
+    if !dotfiles_path.join(".git").exists() {
+        missing_dotfiles_checkout(dotfiles_path);
+    }
+
+    if !identity.exists() {
+        missing_secrets_identity(&identity);
+    }
+
+    let current_dir = env::current_dir().unwrap_or_else(|_| home_dir.clone());
+    let original = normalize(&current_dir.join(&file));
+
+    let Ok(relative) = original.strip_prefix(&home_dir) else {
+        invalid_secret_file(&original, "is not inside the home directory");
+    };
+
+    if !fs::symlink_metadata(&original).is_ok_and(|metadata| metadata.is_file()) {
+        invalid_secret_file(&original, "is not a regular file");
+    }
+
+    let destination = PathBuf::from(format!(
+        "{}.age",
+        dotfiles_path
+            .join(&package)
+            .join(relative)
+            .to_string_lossy()
+    ));
+
+    if destination.exists() {
+        invalid_secret_file(&destination, "already exists in the dotfiles package");
+    }
+
+    let encrypted = destination
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| encrypt(&original, &destination, &identity));
+
+    if let Err(error) = encrypted {
+        secret_failed(&original, "encrypt", error);
+    }
+
+    let mut state = DotfilesState::load();
+    state.record(&package, &original, Some(&destination), DeployedKind::Copy);
+    if let Err(error) = state.save() {
+        warn_dotfiles_state_not_saved(error);
+    }
+
+    secret_added(&original, &destination);
+
+    if commit {
+        let commit_msg = format!("Added secret to {package}: {}", relative.to_string_lossy());
+        commit_dotfiles(&dotfiles_path, &[destination], &commit_msg);
+    }
+}
+
+/// Decrypts a secret into a private temp dir, opens it in the editor and
+/// encrypts it back when it changed.
+pub fn secret_edit(file: PathBuf, commit: bool) {
+    let config = load_config();
+    let dotfiles_path = config.dotfiles_path();
+    let identity = config.secrets_identity_path();
+
+    let secret = match file.exists() {
+        true => normalize(&env::current_dir().unwrap_or_default().join(&file)),
+        false => dotfiles_path.join(&file),
+    };
+
+    if !is_secret(&secret) || !secret.is_file() {
+        invalid_secret_file(&secret, "is not an .age file");
+    }
+
+    if !identity.exists() {
+        missing_secrets_identity(&identity);
+    }
+
+    let original = match decrypt(&secret, &identity) {
+        Ok(original) => original,
+        Err(error) => secret_failed(&secret, "decrypt", error),
+    };
+
+    let private_dir = match PrivateDir::new("secret") {
+        Ok(private_dir) => private_dir,
+        Err(error) => secret_failed(&secret, "decrypt", error),
+    };
+
+    // NOTE: keep the real name so the editor picks the right syntax
+    let file_name = secret.with_extension("");
+    let plaintext = private_dir.join(file_name.file_name().unwrap_or_default());
+
+    if let Err(error) = fs::write(&plaintext, &original) {
+        secret_failed(&secret, "decrypt", error);
+    }
+
+    let editor = preferred_editor();
+    match Command::new(&editor).arg(&plaintext).status() {
+        Ok(status) if status.success() => {}
+        Ok(_) => editor_failed(&editor, None),
+        Err(error) => editor_failed(&editor, Some(error)),
+    }
+
+    let edited = match fs::read(&plaintext) {
+        Ok(edited) if edited == original => return,
+        Ok(edited) => edited,
+        Err(error) => secret_failed(&plaintext, "read", error),
+    };
+
+    if let Err(error) = encrypt(&plaintext, &secret, &identity) {
+        // NOTE: keep the private dir so the edit is not lost with it
+        mem::forget(private_dir);
+        secret_edit_not_saved(&secret, &plaintext, error);
+    }
+
+    drop(private_dir);
+    secret_edited(&secret);
+    refresh_deployed(&secret, &original, &edited);
+
+    if commit {
+        let relative = secret.strip_prefix(&dotfiles_path).unwrap_or(&secret);
+        let commit_msg = format!("Edited secret {}", relative.to_string_lossy());
+        commit_dotfiles(&dotfiles_path, &[secret], &commit_msg);
+    }
+}
+
+/// Rewrites the copies of `secret` that sync deployed so `$HOME` does not
+/// keep the old plaintext, leaving alone the ones changed since.
+fn refresh_deployed(secret: &Path, original: &[u8], edited: &[u8]) {
+    let state = DotfilesState::load();
+
+    for (package, package_state) in &state.packages {
+        let targets = package_state
+            .files
+            .iter()
+            .filter(|file| file.source.as_deref() == Some(secret))
+            .map(|file| &file.target);
+
+        for target in targets {
+            match fs::read(target) {
+                Ok(deployed) if deployed == original => {}
+                Ok(_) => {
+                    warn_secret_copy_stale(target, "it was changed since it was deployed");
+                    continue;
+                }
+                Err(_) => continue,
+            }
+
+            let refreshed = fs::remove_file(target).and_then(|_| write_restricted(target, edited));
+            match refreshed {
+                Ok(_) => dotfile_file_copied(package.into(), target, "updated", false),
+                Err(error) => warn_secret_copy_stale(target, &error.to_string()),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_encrypt_keeps_output_on_failure() {
+    let root = env::temp_dir().join(format!("metl-secrets-{}", std::process::id()));
+    let plaintext = root.join("netrc");
+    let secret = root.join("netrc.age");
+    let identity = root.join("keys.txt");
+
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    fs::write(&plaintext, "machine example.com password hunter2").unwrap();
+    fs::write(&secret, "previous ciphertext").unwrap();
+
+    assert!(is_secret(&secret));
+    assert!(!is_secret(&plaintext));
+
+    // NOTE: fails whether or not age is installed, the identity is missing
+    assert!(encrypt(&plaintext, &secret, &identity).is_err());
+    assert_eq!(fs::read_to_string(&secret).unwrap(), "previous ciphertext");
+    assert!(!root.join("netrc.age.new").exists());
+    assert!(decrypt(&secret, &identity).is_err());
+
+    let generated = Command::new("age-keygen")
+        .arg("--output")
+        .arg(&identity)
+        .output();
+
+    if generated.is_ok_and(|output| output.status.success()) {
+        encrypt(&plaintext, &secret, &identity).unwrap();
+        assert_ne!(fs::read_to_string(&secret).unwrap(), "previous ciphertext");
+        assert_eq!(
+            decrypt(&secret, &identity).unwrap(),
+            b"machine example.com password hunter2"
+        );
+    }
+
+    let _ = fs::remove_dir_all(&root);
+}
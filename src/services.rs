@@ -0,0 +1,206 @@
+use std::{
+    collections::HashSet,
+    io,
+    process::{Command, Stdio},
+};
+
+use crate::{
+    config::Config,
+    manifest::{Manifest, Service, ServiceScope},
+    privileges::{escalated_command, run_escalated},
+    successes::{service_enabled, service_status, services_in_sync},
+    warnings::{warn_service_failed, warn_services_unavailable},
+};
+
+const SCOPES: [ServiceScope; 2] = [ServiceScope::System, ServiceScope::User];
+
+/// A unit `systemctl list-unit-files` reports as enabled.
+#[derive(Debug, PartialEq, Eq)]
+struct EnabledUnit {
+    name: String,
+
+    /// Whether the preset leaves the unit disabled, so someone enabled it
+    customized: bool,
+}
+
+fn systemctl(scope: ServiceScope) -> Command {
+    let mut command = Command::new("systemctl");
+    if scope == ServiceScope::User {
+        command.arg("--user");
+    }
+
+    command
+}
+
+/// Parses `UNIT FILE  STATE  PRESET` rows, older systemd versions print no
+/// preset so every unit counts as customized.
+fn parse_unit_files(output: &str) -> Vec<EnabledUnit> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let name = columns.next()?;
+            let preset = columns.nth(1);
+
+            Some(EnabledUnit {
+                name: name.to_string(),
+                customized: preset != Some("enabled"),
+            })
+        })
+        .collect()
+}
+
+fn enabled_units(scope: ServiceScope) -> io::Result<Vec<EnabledUnit>> {
+    let output = systemctl(scope)
+        .args([
+            "list-unit-files",
+            "--state=enabled",
+            "--no-legend",
+            "--no-pager",
+        ])
+        .stderr(Stdio::null())
+        .output()?;
+
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "systemctl exited with {}",
+            output.status
+        )));
+    }
+
+    Ok(parse_unit_files(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// The units enabled beyond their presets in both scopes. A scope systemctl
+/// can not reach, like the user scope without a session, keeps the services
+/// `previous` had for it.
+pub fn enabled_services(previous: Vec<Service>) -> Vec<Service> {
+    let mut services = vec![];
+
+    for scope in SCOPES {
+        match enabled_units(scope) {
+            Ok(units) => services.extend(units.into_iter().filter(|unit| unit.customized).map(
+                |unit| Service {
+                    name: unit.name,
+                    scope,
+                },
+            )),
+            Err(_) => services.extend(
+                previous
+                    .iter()
+                    .filter(|service| service.scope == scope)
+                    .cloned(),
+            ),
+        }
+    }
+
+    services
+}
+
+/// Enables and starts the manifest services that are not enabled yet.
+pub fn restore_services(config: &Config, manifest: &Manifest, dry_run: bool) {
+    for scope in SCOPES {
+        let wanted: Vec<&Service> = manifest
+            .services
+            .iter()
+            .filter(|service| service.scope == scope)
+            .collect();
+
+        if wanted.is_empty() {
+            continue;
+        }
+
+        let enabled: HashSet<String> = match enabled_units(scope) {
+            Ok(units) => units.into_iter().map(|unit| unit.name).collect(),
+            Err(error) => {
+                warn_services_unavailable(&scope.to_string(), &error.to_string());
+                continue;
+            }
+        };
+
+        for service in wanted {
+            if enabled.contains(&service.name) {
+                continue;
+            }
+
+            if dry_run {
+                service_enabled(&service.name, &scope.to_string(), true);
+                continue;
+            }
+
+            let mut command = match scope {
+                ServiceScope::System => escalated_command("systemctl", &config.escalation),
+                ServiceScope::User => systemctl(scope),
+            };
+
+            match run_escalated(command.args(["enable", "--now"]).arg(&service.name)) {
+                Ok(_) => service_enabled(&service.name, &scope.to_string(), false),
+                Err(error) => warn_service_failed(&service.name, &error.to_string()),
+            }
+        }
+    }
+}
+
+/// Lists manifest services that are not enabled and units enabled beyond
+/// their presets that the manifest does not know about.
+pub fn services_status(manifest: &Manifest) {
+    let mut in_sync = true;
+
+    for scope in SCOPES {
+        let units = match enabled_units(scope) {
+            Ok(units) => units,
+            Err(error) => {
+                warn_services_unavailable(&scope.to_string(), &error.to_string());
+                continue;
+            }
+        };
+
+        let tracked: Vec<&str> = manifest
+            .services
+            .iter()
+            .filter(|service| service.scope == scope)
+            .map(|service| service.name.as_str())
+            .collect();
+        let enabled: HashSet<&str> = units.iter().map(|unit| unit.name.as_str()).collect();
+
+        for name in &tracked {
+            if !enabled.contains(name) {
+                service_status(name, &scope.to_string(), "disabled");
+                in_sync = false;
+            }
+        }
+
+        for unit in units.iter().filter(|unit| unit.customized) {
+            if !tracked.contains(&unit.name.as_str()) {
+                service_status(&unit.name, &scope.to_string(), "untracked");
+                in_sync = false;
+            }
+        }
+    }
+
+    if in_sync && !manifest.services.is_empty() {
+        services_in_sync(manifest.services.len());
+    }
+}
+
+#[test]
+fn test_parse_unit_files() {
+    let output = "\
+sshd.service                 enabled enabled
+docker.service               enabled disabled
+bluetooth.service            enabled disabled
+getty@.service               enabled enabled
+syncthing.service            enabled
+";
+
+    let customized: Vec<String> = parse_unit_files(output)
+        .into_iter()
+        .filter(|unit| unit.customized)
+        .map(|unit| unit.name)
+        .collect();
+
+    assert_eq!(
+        customized,
+        vec!["docker.service", "bluetooth.service", "syncthing.service"]
+    );
+}
@@ -0,0 +1,160 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::get_config_path;
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+pub enum DeployedKind {
+    #[serde(rename(serialize = "link", deserialize = "link"))]
+    Link,
+
+    #[serde(rename(serialize = "copy", deserialize = "copy"))]
+    Copy,
+
+    #[serde(rename(serialize = "dir", deserialize = "dir"))]
+    Dir,
+}
+
+/// A link, copy or directory metl put into `$HOME` for a dotfile package.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct DeployedFile {
+    pub target: PathBuf,
+    pub kind: DeployedKind,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<PathBuf>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup: Option<PathBuf>,
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub struct PackageState {
+    #[serde(default)]
+    pub files: Vec<DeployedFile>,
+}
+
+/// What each dotfile package deployed on this machine, so it can be undone
+/// by `metl dotfiles remove`. Machine specific, so it is kept out of the
+/// manifest repo.
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct DotfilesState {
+    #[serde(default)]
+    pub packages: BTreeMap<String, PackageState>,
+
+    #[serde(skip)]
+    pending_backups: HashMap<PathBuf, PathBuf>,
+}
+
+pub fn get_state_path() -> PathBuf {
+    get_config_path().join("state")
+}
+
+impl DotfilesState {
+    pub fn load() -> Self {
+        fs::read_to_string(get_state_path().join("dotfiles.toml"))
+            .ok()
+            .and_then(|contents| toml::from_str::<DotfilesState>(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let state_path = get_state_path();
+        fs::create_dir_all(&state_path)?;
+
+        let gitignore = state_path.join(".gitignore");
+        if !gitignore.exists() {
+            fs::write(gitignore, "*\n")?;
+        }
+
+        let contents = toml::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(state_path.join("dotfiles.toml"), contents)
+    }
+
+    /// Remembers where `target` was backed up so the next `record` for it
+    /// can restore it on removal.
+    pub fn record_backup(&mut self, target: &Path, backup: &Path) {
+        self.pending_backups
+            .insert(target.to_path_buf(), backup.to_path_buf());
+    }
+
+    pub fn record(
+        &mut self,
+        package: &str,
+        target: &Path,
+        source: Option<&Path>,
+        kind: DeployedKind,
+    ) {
+        let previous = self.forget(target);
+        let backup = self
+            .pending_backups
+            .remove(target)
+            .or(previous.and_then(|file| file.backup));
+
+        self.packages
+            .entry(package.to_string())
+            .or_default()
+            .files
+            .push(DeployedFile {
+                target: target.to_path_buf(),
+                kind,
+                source: source.map(Path::to_path_buf),
+                backup,
+            });
+    }
+
+    /// Drops `target` from whichever package owned it.
+    pub fn forget(&mut self, target: &Path) -> Option<DeployedFile> {
+        self.packages.values_mut().find_map(|package| {
+            let index = package.files.iter().position(|f| f.target == target)?;
+            Some(package.files.remove(index))
+        })
+    }
+}
+
+/// The package a path inside the dotfiles checkout belongs to.
+pub fn owning_package(dotfiles_path: &Path, source: &Path) -> Option<String> {
+    source
+        .strip_prefix(dotfiles_path)
+        .ok()?
+        .components()
+        .next()
+        .map(|component| component.as_os_str().to_string_lossy().to_string())
+}
+
+#[test]
+fn test_record_carries_backups() {
+    let mut state = DotfilesState::default();
+    let config = PathBuf::from("/home/user/.config");
+    let source = PathBuf::from("/home/user/dotfiles/nvim/.config");
+
+    state.record_backup(&config, Path::new("/backups/1/.config"));
+    state.record("nvim", &config, Some(&source), DeployedKind::Link);
+
+    let unfolded = state.forget(&config).unwrap();
+    assert_eq!(unfolded.backup, Some(PathBuf::from("/backups/1/.config")));
+    assert!(state.packages["nvim"].files.is_empty());
+
+    state.record("nvim", &config, Some(&source), DeployedKind::Link);
+    state.record("fish", &config, None, DeployedKind::Dir);
+    assert!(state.packages["nvim"].files.is_empty());
+    assert_eq!(
+        state.packages["fish"].files,
+        vec![DeployedFile {
+            target: config,
+            kind: DeployedKind::Dir,
+            source: None,
+            backup: None,
+        }]
+    );
+
+    assert_eq!(
+        owning_package(Path::new("/home/user/dotfiles"), &source),
+        Some("nvim".to_string())
+    );
+}
@@ -0,0 +1,57 @@
+use std::{collections::HashSet, path::Path};
+
+use crate::{
+    errors::pacman_db_error,
+    manifest::load_manifest,
+    pacman_db::{InstallReason, PACMAN_DB_PATH, SyncDatabases, installed_packages},
+    services::services_status,
+    successes::{package_status, packages_in_sync},
+};
+
+/// Compares the manifest with pacman's local database, listing manifest
+/// packages that are not installed and explicit installs the manifest does
+/// not know about, then the same for services. Groups count as all of their
+/// members.
+pub fn manifest_status() {
+    let manifest = load_manifest();
+    let db_path = Path::new(PACMAN_DB_PATH);
+    let sync = SyncDatabases::load(db_path);
+
+    let installed = match installed_packages(db_path, &sync) {
+        Ok(installed) => installed,
+        Err(error) => pacman_db_error(error),
+    };
+
+    let installed_names: HashSet<&str> = installed
+        .iter()
+        .map(|package| package.name.as_str())
+        .collect();
+
+    let expanded = manifest.expanded_packages(&sync.groups);
+    let tracked: HashSet<&str> = expanded
+        .iter()
+        .map(|package| package.name.as_str())
+        .collect();
+
+    let mut in_sync = true;
+
+    for package in &expanded {
+        if !installed_names.contains(package.name.as_str()) {
+            package_status(&package.name, "missing", None);
+            in_sync = false;
+        }
+    }
+
+    for package in &installed {
+        if package.reason == InstallReason::Explicit && !tracked.contains(package.name.as_str()) {
+            package_status(&package.name, "untracked", Some(package));
+            in_sync = false;
+        }
+    }
+
+    if in_sync {
+        packages_in_sync(expanded.len());
+    }
+
+    services_status(&manifest);
+}
@@ -1,12 +1,26 @@
-use std::{ffi::OsString, path::PathBuf, process::Output, sync::LazyLock};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
 
+use crate::{
+    import_log::HistoryStep,
+    manifest::PackageManager,
+    output::{Event, emit, is_json},
+    pacman_db::InstalledPackage,
+};
 use colored::{ColoredString, Colorize};
 
-use crate::{config::load_config, manifest::PackageManager};
-
 static SUCCESS: LazyLock<ColoredString> = LazyLock::new(|| "[SUCCESS]".green().bold());
 
 pub fn packages_retrieved_successfully(manager: PackageManager) {
+    if is_json() {
+        return emit(Event::ManifestGenerated {
+            manager: manager.to_string(),
+        });
+    }
+
     println!(
         "{} {} {}",
         &*SUCCESS,
@@ -20,6 +34,17 @@ pub fn package_sync_success(
     packages: &[String],
     install_errors: &[(&String, Option<std::io::Error>)],
 ) {
+    if is_json() {
+        return emit(Event::PackagesSynced {
+            manager: manager.to_string(),
+            packages: packages.to_vec(),
+            failed: install_errors
+                .iter()
+                .map(|(package, _)| package.to_string())
+                .collect(),
+        });
+    }
+
     if install_errors.is_empty() {
         println!(
             "{} {} {} {}",
@@ -47,7 +72,72 @@ pub fn package_sync_success(
     }
 }
 
+pub fn dotfiles_cloned(repo: &str, dotfiles_path: &Path) {
+    if is_json() {
+        return emit(Event::DotfilesCloned {
+            repo: repo.to_string(),
+            path: dotfiles_path.to_string_lossy().to_string(),
+            dry_run: false,
+        });
+    }
+
+    println!(
+        "{} {} {} {}",
+        &*SUCCESS,
+        "cloned".white().dimmed(),
+        repo.white().bold(),
+        dotfiles_path.to_string_lossy().white().bold()
+    );
+}
+
+pub fn dotfiles_updated(dotfiles_path: &Path) {
+    if is_json() {
+        return emit(Event::DotfilesUpdated {
+            path: dotfiles_path.to_string_lossy().to_string(),
+            dry_run: false,
+        });
+    }
+
+    println!(
+        "{} {} {}",
+        &*SUCCESS,
+        "fast-forwarded".white().dimmed(),
+        dotfiles_path.to_string_lossy().white().bold()
+    );
+}
+
+pub fn dry_run_dotfiles_update(dotfiles_path: &Path, git_ref: &str) {
+    if is_json() {
+        return emit(Event::DotfilesUpdated {
+            path: dotfiles_path.to_string_lossy().to_string(),
+            dry_run: true,
+        });
+    }
+
+    println!(
+        "{} {} {} {}",
+        &*SUCCESS,
+        "DRY RUN:".yellow(),
+        match git_ref {
+            "" => "git fetch && git merge --ff-only in".white(),
+            git_ref => format!("git fetch && git checkout {git_ref} in").white(),
+        },
+        dotfiles_path
+            .to_string_lossy()
+            .truecolor(255, 255, 255)
+            .bold()
+    );
+}
+
 pub fn dry_run_dotfiles_clone(repo: &str, dotfiles_path: PathBuf) {
+    if is_json() {
+        return emit(Event::DotfilesCloned {
+            repo: repo.to_string(),
+            path: dotfiles_path.to_string_lossy().to_string(),
+            dry_run: true,
+        });
+    }
+
     println!(
         "{} {} {} {} {}",
         &*SUCCESS,
@@ -61,41 +151,106 @@ pub fn dry_run_dotfiles_clone(repo: &str, dotfiles_path: PathBuf) {
     );
 }
 
-pub fn stow_success(name: OsString) {
+pub fn dotfile_linked(name: OsString, target: &Path, source: &Path, dry_run: bool) {
+    if is_json() {
+        return emit(Event::DotfileLinked {
+            name: name.to_string_lossy().to_string(),
+            target: target.to_string_lossy().to_string(),
+            source: source.to_string_lossy().to_string(),
+            dry_run,
+        });
+    }
+
     println!(
-        "{} {} {} {}",
+        "{} {}{} {} {} {}",
         &*SUCCESS,
-        "stow".white().bold(),
-        "symlinked".white().dimmed(),
+        if dry_run {
+            "DRY RUN: ".yellow()
+        } else {
+            "".normal()
+        },
         name.to_string_lossy().white().bold(),
+        target.to_string_lossy().white().bold(),
+        "->".white().dimmed(),
+        source.to_string_lossy().white(),
     );
 }
 
-pub fn dotfiles_copied_successfully(name: OsString, to: PathBuf, output: Output, verbose: bool) {
+pub fn dotfiles_linked_successfully(name: OsString) {
+    if is_json() {
+        return;
+    }
+
     println!(
-        "{} {} {} {}",
+        "{} {} {}",
         &*SUCCESS,
+        "symlinked".white().dimmed(),
         name.to_string_lossy().white().bold(),
-        "copied successfully to".white(),
-        to.to_string_lossy().white().bold(),
     );
+}
+
+pub fn dotfile_skipped(name: OsString, reason: &str, verbose: bool) {
+    if is_json() {
+        return emit(Event::DotfileSkipped {
+            name: name.to_string_lossy().to_string(),
+            reason: reason.to_string(),
+        });
+    }
 
     if verbose {
-        if let Ok(stdout) = String::from_utf8(output.stdout)
-            && !stdout.trim().is_empty()
-        {
-            println!("{stdout}");
-        }
+        println!(
+            "{} {} {} {}",
+            &*SUCCESS,
+            name.to_string_lossy().white().bold(),
+            "skipped:".white().dimmed(),
+            reason.white(),
+        );
+    }
+}
 
-        if let Ok(stderr) = String::from_utf8(output.stderr)
-            && !stderr.trim().is_empty()
-        {
-            println!("{stderr}");
-        }
+pub fn dotfile_file_copied(name: OsString, target: &Path, action: &str, dry_run: bool) {
+    if is_json() {
+        return emit(Event::DotfileCopied {
+            name: name.to_string_lossy().to_string(),
+            target: target.to_string_lossy().to_string(),
+            action: action.to_string(),
+            dry_run,
+        });
     }
+
+    println!(
+        "{} {}{} {} {}",
+        &*SUCCESS,
+        if dry_run {
+            "DRY RUN: ".yellow()
+        } else {
+            "".normal()
+        },
+        name.to_string_lossy().white().bold(),
+        format!("{action}:").white().dimmed(),
+        target.to_string_lossy().white().bold(),
+    );
+}
+
+pub fn dotfiles_copied_successfully(name: OsString, to: PathBuf) {
+    if is_json() {
+        return;
+    }
+
+    println!(
+        "{} {} {} {}",
+        &*SUCCESS,
+        name.to_string_lossy().white().bold(),
+        "copied successfully to".white(),
+        to.to_string_lossy().white().bold(),
+    );
 }
 
 pub fn pacman_dry_run_header() {
+    if is_json() {
+        return;
+    }
+
     println!(
         "{} {} {}",
         &*SUCCESS,
@@ -105,6 +260,13 @@ pub fn pacman_dry_run_header() {
 }
 
 pub fn install_successful(package_manager: &PackageManager, installed: &str) {
+    if is_json() {
+        return emit(Event::PackageInstalled {
+            manager: package_manager.to_string(),
+            package: installed.to_string(),
+        });
+    }
+
     println!(
         "{} {} {} {}",
         &*SUCCESS,
@@ -115,6 +277,13 @@ pub fn install_successful(package_manager: &PackageManager, installed: &str) {
 }
 
 pub fn remove_successful(package_manager: &PackageManager, installed: &str) {
+    if is_json() {
+        return emit(Event::PackageRemoved {
+            manager: package_manager.to_string(),
+            package: installed.to_string(),
+        });
+    }
+
     println!(
         "{} {} {} {}",
         &*SUCCESS,
@@ -125,6 +294,13 @@ pub fn remove_successful(package_manager: &PackageManager, installed: &str) {
 }
 
 pub fn package_update_success(manager: &PackageManager, package: &str) {
+    if is_json() {
+        return emit(Event::PackageInstalled {
+            manager: manager.to_string(),
+            package: package.to_string(),
+        });
+    }
+
     println!(
         "{} {} {} {}",
         &*SUCCESS,
@@ -134,24 +310,621 @@ pub fn package_update_success(manager: &PackageManager, package: &str) {
     );
 }
 
-pub fn git_metl_manifest_commit_success(proxied_command: &str) {
-    let config = load_config();
+pub fn git_metl_manifest_commit_success(repo: &str, commit_msg: &str) {
+    if is_json() {
+        return emit(Event::CommitCreated {
+            repo: repo.to_string(),
+            message: commit_msg.to_string(),
+        });
+    }
 
     println!(
-        "{} {} {} {}",
+        "{} {} {}",
         &*SUCCESS,
-        "metl manifest files committed after command: "
+        format!("{repo} files committed:").as_str().white().dimmed(),
+        commit_msg.cyan(),
+    );
+}
+
+pub fn git_push_metl_manifest_success(repo: &str) {
+    if is_json() {
+        return emit(Event::CommitPushed {
+            repo: repo.to_string(),
+        });
+    }
+
+    println!(
+        "{} {}",
+        &*SUCCESS,
+        format!("{repo} files pushed to remote git")
+            .as_str()
             .white()
             .dimmed(),
-        config.package_manager.to_string().white().bold(),
-        proxied_command.cyan(),
     );
 }
 
-pub fn git_push_metl_manifest_success() {
+pub fn config_value_set(key: &str, value: &str) {
+    if is_json() {
+        return emit(Event::ConfigChanged {
+            key: key.to_string(),
+            value: Some(value.to_string()),
+        });
+    }
+
+    println!(
+        "{} {} {} {} {}",
+        &*SUCCESS,
+        "config".white().dimmed(),
+        key.white().bold(),
+        "set to".white().dimmed(),
+        value.cyan(),
+    );
+}
+
+pub fn config_value_unset(key: &str) {
+    if is_json() {
+        return emit(Event::ConfigChanged {
+            key: key.to_string(),
+            value: None,
+        });
+    }
+
+    println!(
+        "{} {} {} {}",
+        &*SUCCESS,
+        "config".white().dimmed(),
+        key.white().bold(),
+        "unset".white().dimmed(),
+    );
+}
+
+pub fn config_edited(config_path: PathBuf) {
+    if is_json() {
+        return emit(Event::ConfigChanged {
+            key: config_path.to_string_lossy().to_string(),
+            value: None,
+        });
+    }
+
+    println!(
+        "{} {} {}",
+        &*SUCCESS,
+        "config saved:".white().dimmed(),
+        config_path.to_string_lossy().white().bold(),
+    );
+}
+
+pub fn dotfile_backed_up(target: &Path, backup: &Path, dry_run: bool) {
+    if is_json() {
+        return emit(Event::DotfileBackedUp {
+            target: target.to_string_lossy().to_string(),
+            backup: backup.to_string_lossy().to_string(),
+            dry_run,
+        });
+    }
+
+    println!(
+        "{} {}{} {} {}",
+        &*SUCCESS,
+        if dry_run {
+            "DRY RUN: ".yellow()
+        } else {
+            "".normal()
+        },
+        target.to_string_lossy().white().bold(),
+        "backed up to".white().dimmed(),
+        backup.to_string_lossy().white(),
+    );
+}
+
+pub fn backups_saved(id: &str, dir: &Path) {
+    if is_json() {
+        return;
+    }
+
+    println!(
+        "{} {} {} {} {}",
+        &*SUCCESS,
+        "conflicting files backed up to".white().dimmed(),
+        dir.to_string_lossy().white().bold(),
+        "undo with: metl dotfiles restore-backup".white().dimmed(),
+        id.cyan(),
+    );
+}
+
+pub fn dotfile_restored(target: &Path, backup: &Path) {
+    if is_json() {
+        return emit(Event::DotfileRestored {
+            target: target.to_string_lossy().to_string(),
+            backup: backup.to_string_lossy().to_string(),
+        });
+    }
+
+    println!(
+        "{} {} {} {}",
+        &*SUCCESS,
+        target.to_string_lossy().white().bold(),
+        "restored from".white().dimmed(),
+        backup.to_string_lossy().white(),
+    );
+}
+
+pub fn backup_session_listed(id: &str, files: usize) {
+    if is_json() {
+        return emit(Event::BackupListed {
+            id: id.to_string(),
+            files,
+        });
+    }
+
+    println!(
+        "{} {} {}",
+        id.white().bold(),
+        files.to_string().cyan(),
+        "files".white().dimmed(),
+    );
+}
+
+pub fn dotfile_adopted(original: &Path, destination: &Path) {
+    if is_json() {
+        return emit(Event::DotfileAdopted {
+            original: original.to_string_lossy().to_string(),
+            destination: destination.to_string_lossy().to_string(),
+        });
+    }
+
+    println!(
+        "{} {} {} {}",
+        &*SUCCESS,
+        original.to_string_lossy().white().bold(),
+        "adopted into".white().dimmed(),
+        destination.to_string_lossy().white(),
+    );
+}
+
+pub fn dotfile_removed(target: &Path, restored: Option<&Path>, dry_run: bool) {
+    if is_json() {
+        return emit(Event::DotfileRemoved {
+            target: target.to_string_lossy().to_string(),
+            restored: restored.map(|backup| backup.to_string_lossy().to_string()),
+            dry_run,
+        });
+    }
+
+    println!(
+        "{} {}{} {}",
+        &*SUCCESS,
+        if dry_run {
+            "DRY RUN: ".yellow()
+        } else {
+            "".normal()
+        },
+        target.to_string_lossy().white().bold(),
+        match restored {
+            Some(backup) => format!(
+                "{} {}",
+                "removed, restored from".white().dimmed(),
+                backup.to_string_lossy().white()
+            ),
+            None => "removed".white().dimmed().to_string(),
+        },
+    );
+}
+
+/// Formats a size in bytes the way pacman does, in binary units.
+fn human_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    let mut unit = "B";
+
+    for next in ["KiB", "MiB", "GiB"] {
+        if size < 1024.0 {
+            break;
+        }
+
+        size /= 1024.0;
+        unit = next;
+    }
+
+    match unit {
+        "B" => format!("{bytes} B"),
+        unit => format!("{size:.1} {unit}"),
+    }
+}
+
+pub fn package_status(package: &str, status: &str, installed: Option<&InstalledPackage>) {
+    if is_json() {
+        return emit(Event::PackageStatus {
+            package: package.to_string(),
+            status: status.to_string(),
+            installed_at: installed
+                .and_then(InstalledPackage::installed_at)
+                .map(|installed_at| installed_at.to_string()),
+            size: installed.map(|installed| installed.size),
+            depends: installed
+                .map(|installed| installed.depends.clone())
+                .unwrap_or_default(),
+        });
+    }
+
+    let padded = format!("{status:>9}");
+    let status = match status {
+        "missing" => padded.yellow(),
+        _ => padded.cyan(),
+    };
+
+    let details = installed
+        .map(|installed| {
+            let since = installed
+                .installed_at()
+                .and_then(|installed_at| installed_at.date)
+                .map(|date| format!("installed outside metl since {date}, "))
+                .unwrap_or_default();
+
+            format!(
+                "{since}{}, {} dependencies",
+                human_size(installed.size),
+                installed.depends.len()
+            )
+        })
+        .unwrap_or_default();
+
+    println!(
+        "{} {} {}",
+        status.bold(),
+        package.white().bold(),
+        details.white().dimmed(),
+    );
+}
+
+pub fn packages_in_sync(packages: usize) {
+    if is_json() {
+        return emit(Event::PackagesInSync { packages });
+    }
+
+    println!(
+        "{} {} {}",
+        &*SUCCESS,
+        packages.to_string().white().bold(),
+        "manifest packages installed, nothing untracked".white()
+    );
+}
+
+pub fn manifest_history(step: &HistoryStep) {
+    if is_json() {
+        return emit(Event::ManifestHistory {
+            timestamp: step.timestamp.clone(),
+            command: step.command.clone(),
+            added: step.added.clone(),
+            removed: step.removed.clone(),
+        });
+    }
+
+    // NOTE: both log formats start with the date
+    let date = step.timestamp.get(..10).unwrap_or(&step.timestamp);
+    let command = step.command.as_deref().unwrap_or_default();
+
+    step.added.iter().for_each(|package| {
+        println!(
+            "{} {} {} {}",
+            date.white().dimmed(),
+            "+".green().bold(),
+            package.white().bold(),
+            command.white().dimmed(),
+        )
+    });
+
+    step.removed.iter().for_each(|package| {
+        println!(
+            "{} {} {} {}",
+            date.white().dimmed(),
+            "-".red().bold(),
+            package.white().bold(),
+            command.white().dimmed(),
+        )
+    });
+}
+
+pub fn manifest_history_imported(commits: usize) {
+    if is_json() {
+        return emit(Event::ManifestHistoryImported { commits });
+    }
+
+    println!(
+        "{} {} {}",
+        &*SUCCESS,
+        "imported pacman.log history as".white(),
+        format!("{commits} backdated commits").white().bold(),
+    );
+}
+
+pub fn etc_file_status(path: &Path, package: &str, status: &str) {
+    if is_json() {
+        return emit(Event::EtcFileStatus {
+            path: path.to_string_lossy().to_string(),
+            package: package.to_string(),
+            status: status.to_string(),
+        });
+    }
+
+    let padded = format!("{status:>10}");
+    let status = match status {
+        "tracked" => padded.green(),
+        "modified" => padded.cyan(),
+        "missing" | "drifted" => padded.yellow(),
+        _ => padded.red(),
+    };
+
+    println!(
+        "{} {} {}",
+        status.bold(),
+        path.to_string_lossy().white().bold(),
+        package.white().dimmed(),
+    );
+}
+
+pub fn etc_file_tracked(path: &Path, tracked: bool) {
+    if is_json() {
+        return emit(Event::EtcFileTracked {
+            path: path.to_string_lossy().to_string(),
+            tracked,
+        });
+    }
+
+    let action = match tracked {
+        true => "tracking",
+        false => "stopped tracking",
+    };
+
+    println!(
+        "{} {} {}",
+        &*SUCCESS,
+        action.white(),
+        path.to_string_lossy().white().bold(),
+    );
+}
+
+pub fn etc_file_diff(path: &Path, diff: &str) {
+    if is_json() {
+        return emit(Event::EtcFileDiff {
+            path: path.to_string_lossy().to_string(),
+            diff: diff.to_string(),
+        });
+    }
+
+    print!("{diff}");
+}
+
+pub fn etc_file_restored(path: &Path, dry_run: bool) {
+    if is_json() {
+        return emit(Event::EtcFileRestored {
+            path: path.to_string_lossy().to_string(),
+            dry_run,
+        });
+    }
+
+    let action = match dry_run {
+        true => "would restore",
+        false => "restored",
+    };
+
+    println!(
+        "{} {} {}",
+        &*SUCCESS,
+        action.white(),
+        path.to_string_lossy().white().bold(),
+    );
+}
+
+pub fn service_status(service: &str, scope: &str, status: &str) {
+    if is_json() {
+        return emit(Event::ServiceStatus {
+            service: service.to_string(),
+            scope: scope.to_string(),
+            status: status.to_string(),
+        });
+    }
+
+    let padded = format!("{status:>9}");
+    let status = match status {
+        "disabled" => padded.yellow(),
+        _ => padded.cyan(),
+    };
+
+    println!(
+        "{} {} {}",
+        status.bold(),
+        service.white().bold(),
+        scope.white().dimmed(),
+    );
+}
+
+pub fn services_in_sync(services: usize) {
+    if is_json() {
+        return emit(Event::ServicesInSync { services });
+    }
+
+    println!(
+        "{} {} {}",
+        &*SUCCESS,
+        services.to_string().white().bold(),
+        "manifest services enabled, nothing untracked".white()
+    );
+}
+
+pub fn service_enabled(service: &str, scope: &str, dry_run: bool) {
+    if is_json() {
+        return emit(Event::ServiceEnabled {
+            service: service.to_string(),
+            scope: scope.to_string(),
+            dry_run,
+        });
+    }
+
+    let action = match dry_run {
+        true => "would enable and start",
+        false => "enabled and started",
+    };
+
+    println!(
+        "{} {} {} {}",
+        &*SUCCESS,
+        action.white(),
+        service.white().bold(),
+        scope.white().dimmed(),
+    );
+}
+
+pub fn repository_key_imported(key: &str, dry_run: bool) {
+    if is_json() {
+        return emit(Event::RepositoryKeyImported {
+            key: key.to_string(),
+            dry_run,
+        });
+    }
+
+    let action = match dry_run {
+        true => "would import and sign key",
+        false => "imported and signed key",
+    };
+
+    println!("{} {} {}", &*SUCCESS, action.white(), key.white().bold());
+}
+
+pub fn repository_added(repository: &str, dry_run: bool) {
+    if is_json() {
+        return emit(Event::RepositoryAdded {
+            repository: repository.to_string(),
+            dry_run,
+        });
+    }
+
+    let action = match dry_run {
+        true => "would add repository",
+        false => "added repository",
+    };
+
+    println!(
+        "{} {} {} {}",
+        &*SUCCESS,
+        action.white(),
+        repository.white().bold(),
+        "to pacman.conf".white(),
+    );
+}
+
+pub fn repositories_refreshed() {
+    if is_json() {
+        return emit(Event::RepositoriesRefreshed);
+    }
+
     println!(
         "{} {}",
         &*SUCCESS,
-        "metl manifest files pushed to remote git".white().dimmed(),
+        "refreshed the pacman package databases".white()
+    );
+}
+
+pub fn hook_installed(hook_path: &Path) {
+    if is_json() {
+        return emit(Event::HookInstalled {
+            path: hook_path.to_string_lossy().to_string(),
+        });
+    }
+
+    println!(
+        "{} {} {}",
+        &*SUCCESS,
+        "installed the pacman hook at".white(),
+        hook_path.to_string_lossy().white().bold(),
+    );
+}
+
+pub fn hook_removed(hook_path: &Path, existed: bool) {
+    if is_json() {
+        return emit(Event::HookRemoved {
+            path: hook_path.to_string_lossy().to_string(),
+            existed,
+        });
+    }
+
+    let action = match existed {
+        true => "removed the pacman hook at",
+        false => "no pacman hook installed at",
+    };
+
+    println!(
+        "{} {} {}",
+        &*SUCCESS,
+        action.white(),
+        hook_path.to_string_lossy().white().bold(),
+    );
+}
+
+pub fn dotfile_status(package: &str, target: &Path, status: &str) {
+    if is_json() {
+        return emit(Event::DotfileStatus {
+            package: package.to_string(),
+            target: target.to_string_lossy().to_string(),
+            status: status.to_string(),
+        });
+    }
+
+    let padded = format!("{status:>9}");
+    let status = match status {
+        "linked" | "unchanged" => padded.green(),
+        "missing" => padded.yellow(),
+        "modified" => padded.cyan(),
+        _ => padded.red(),
+    };
+
+    println!(
+        "{} {} {}",
+        status.bold(),
+        package.white().bold(),
+        target.to_string_lossy().white(),
+    );
+}
+
+pub fn dotfile_diff(target: &Path, source: &Path, diff: &str) {
+    if is_json() {
+        return emit(Event::DotfileDiff {
+            target: target.to_string_lossy().to_string(),
+            source: source.to_string_lossy().to_string(),
+            diff: diff.to_string(),
+        });
+    }
+
+    print!("{diff}");
+}
+
+pub fn secret_added(original: &Path, destination: &Path) {
+    if is_json() {
+        return emit(Event::SecretAdded {
+            original: original.to_string_lossy().to_string(),
+            destination: destination.to_string_lossy().to_string(),
+        });
+    }
+
+    println!(
+        "{} {} {} {}",
+        &*SUCCESS,
+        original.to_string_lossy().white().bold(),
+        "encrypted into".white().dimmed(),
+        destination.to_string_lossy().white(),
+    );
+}
+
+pub fn secret_edited(path: &Path) {
+    if is_json() {
+        return emit(Event::SecretEdited {
+            path: path.to_string_lossy().to_string(),
+        });
+    }
+
+    println!(
+        "{} {} {}",
+        &*SUCCESS,
+        "re-encrypted".white().dimmed(),
+        path.to_string_lossy().white().bold(),
     );
 }
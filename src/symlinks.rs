@@ -0,0 +1,344 @@
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs, io,
+    os::unix,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{copies::is_rendered, ignores::IgnoreRules};
+
+/// A single step needed to link a dotfile package into the target dir,
+/// mirroring what `stow -S` would do.
+#[derive(Debug, PartialEq, Eq)]
+pub enum LinkAction {
+    CreateLink { target: PathBuf, source: PathBuf },
+    CreateDir { target: PathBuf },
+    RemoveLink { target: PathBuf },
+    AlreadyLinked { target: PathBuf, source: PathBuf },
+    Conflict { target: PathBuf, reason: String },
+    Backup { target: PathBuf },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Missing,
+    Dir,
+    File,
+    Link(PathBuf),
+}
+
+struct Planner<'a> {
+    stow_dir: &'a Path,
+    ignores: &'a IgnoreRules,
+    backup_conflicts: bool,
+    planned: HashMap<PathBuf, Node>,
+    unfolded: Vec<PathBuf>,
+    actions: Vec<LinkAction>,
+}
+
+/// Rendered files deploy as copies and ignored ones not at all, so a
+/// directory holding either must be a real directory rather than a link into
+/// the checkout.
+fn needs_real_dir(dir: &Path, ignores: &IgnoreRules) -> bool {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return false;
+    };
+
+    entries.flatten().any(|entry| {
+        let path = entry.path();
+        let is_dir = path.is_dir();
+
+        match ignores.is_ignored(&path, is_dir) {
+            true => true,
+            false if is_dir => needs_real_dir(&path, ignores),
+            false => is_rendered(&path),
+        }
+    })
+}
+
+/// Plans linking `stow_dir/package` into `target_dir`. Missing directories
+/// are folded into a single link, and a folded directory owned by another
+/// package is unfolded so both packages can share it. Templates and secrets
+/// are left out, they are copied in by sync, as is anything `ignores`
+/// matches. With `backup_conflicts` anything in the way is planned to be
+/// moved aside instead of reported as a conflict.
+pub fn plan_package(
+    stow_dir: &Path,
+    package: &OsStr,
+    target_dir: &Path,
+    ignores: &IgnoreRules,
+    backup_conflicts: bool,
+) -> io::Result<Vec<LinkAction>> {
+    let mut planner = Planner {
+        stow_dir,
+        ignores,
+        backup_conflicts,
+        planned: HashMap::new(),
+        unfolded: vec![],
+        actions: vec![],
+    };
+
+    planner.plan_children(&stow_dir.join(package), target_dir)?;
+
+    Ok(planner.actions)
+}
+
+pub fn apply_action(action: &LinkAction) -> io::Result<()> {
+    match action {
+        LinkAction::CreateLink { target, source } => {
+            let parent = target.parent().unwrap_or(Path::new("/"));
+            unix::fs::symlink(relative_path(parent, source), target)
+        }
+        LinkAction::CreateDir { target } => fs::create_dir(target),
+        LinkAction::RemoveLink { target } => fs::remove_file(target),
+        LinkAction::AlreadyLinked { .. }
+        | LinkAction::Conflict { .. }
+        | LinkAction::Backup { .. } => Ok(()),
+    }
+}
+
+impl Planner<'_> {
+    fn plan_children(&mut self, source_dir: &Path, target_dir: &Path) -> io::Result<()> {
+        let mut children: Vec<_> = fs::read_dir(source_dir)?
+            .flatten()
+            .filter(|entry| {
+                let source = entry.path();
+                let is_dir = source.is_dir();
+                !self.ignores.is_ignored(&source, is_dir) && (is_dir || !is_rendered(&source))
+            })
+            .map(|entry| entry.file_name())
+            .collect();
+        children.sort();
+
+        for name in children {
+            self.plan_entry(&source_dir.join(&name), &target_dir.join(&name))?;
+        }
+
+        Ok(())
+    }
+
+    fn plan_entry(&mut self, source: &Path, target: &Path) -> io::Result<()> {
+        match self.node(target) {
+            Node::Missing if source.is_dir() && needs_real_dir(source, self.ignores) => {
+                self.create_dir(target);
+                self.plan_children(source, target)?;
+            }
+
+            Node::Missing => self.link(target, source),
+
+            Node::Link(dest)
+                if dest == source && source.is_dir() && needs_real_dir(source, self.ignores) =>
+            {
+                self.unfold(target, &dest)?;
+            }
+
+            Node::Link(dest) if dest == source => {
+                self.actions.push(LinkAction::AlreadyLinked {
+                    target: target.to_path_buf(),
+                    source: source.to_path_buf(),
+                });
+            }
+
+            Node::Link(dest)
+                if dest.starts_with(self.stow_dir) && dest.is_dir() && source.is_dir() =>
+            {
+                self.unfold(target, &dest)?;
+                self.plan_children(source, target)?;
+            }
+
+            Node::Link(dest) => self.conflict(
+                source,
+                target,
+                format!("existing link points to {}", dest.to_string_lossy()),
+            ),
+
+            Node::Dir if source.is_dir() => self.plan_children(source, target)?,
+            Node::Dir => self.conflict(source, target, "existing directory".to_string()),
+            Node::File => self.conflict(source, target, "existing file".to_string()),
+        }
+
+        Ok(())
+    }
+
+    /// Replaces a folded directory link with a real directory containing one
+    /// link per child of the package that owned it.
+    fn unfold(&mut self, target: &Path, dest: &Path) -> io::Result<()> {
+        self.actions.push(LinkAction::RemoveLink {
+            target: target.to_path_buf(),
+        });
+        self.create_dir(target);
+        self.unfolded.push(target.to_path_buf());
+
+        self.plan_children(dest, target)
+    }
+
+    fn create_dir(&mut self, target: &Path) {
+        self.planned.insert(target.to_path_buf(), Node::Dir);
+        self.actions.push(LinkAction::CreateDir {
+            target: target.to_path_buf(),
+        });
+    }
+
+    fn link(&mut self, target: &Path, source: &Path) {
+        self.planned
+            .insert(target.to_path_buf(), Node::Link(source.to_path_buf()));
+        self.actions.push(LinkAction::CreateLink {
+            target: target.to_path_buf(),
+            source: source.to_path_buf(),
+        });
+    }
+
+    fn conflict(&mut self, source: &Path, target: &Path, reason: String) {
+        if self.backup_conflicts {
+            self.actions.push(LinkAction::Backup {
+                target: target.to_path_buf(),
+            });
+            return self.link(target, source);
+        }
+
+        self.actions.push(LinkAction::Conflict {
+            target: target.to_path_buf(),
+            reason,
+        });
+    }
+
+    fn node(&self, path: &Path) -> Node {
+        if let Some(node) = self.planned.get(path) {
+            return node.clone();
+        }
+
+        // NOTE: on disk this still resolves through the link being removed
+        if self.unfolded.iter().any(|dir| path.starts_with(dir)) {
+            return Node::Missing;
+        }
+
+        let Ok(metadata) = fs::symlink_metadata(path) else {
+            return Node::Missing;
+        };
+
+        if metadata.is_symlink() {
+            let Ok(link) = fs::read_link(path) else {
+                return Node::File;
+            };
+
+            let parent = path.parent().unwrap_or(Path::new("/"));
+            return Node::Link(normalize(&parent.join(link)));
+        }
+
+        match metadata.is_dir() {
+            true => Node::Dir,
+            false => Node::File,
+        }
+    }
+}
+
+/// Lexically resolves `.` and `..` so dangling links can still be compared.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+fn relative_path(from_dir: &Path, to: &Path) -> PathBuf {
+    let (from_dir, to) = (normalize(from_dir), normalize(to));
+    let from: Vec<_> = from_dir.components().collect();
+    let to_components: Vec<_> = to.components().collect();
+
+    let common = from
+        .iter()
+        .zip(to_components.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut relative = PathBuf::new();
+    from[common..].iter().for_each(|_| relative.push(".."));
+    to_components[common..]
+        .iter()
+        .for_each(|component| relative.push(component));
+
+    relative
+}
+
+#[test]
+fn test_plan_package_folds_and_unfolds() {
+    let root = std::env::temp_dir().join(format!("metl-symlinks-{}", std::process::id()));
+    let stow_dir = root.join("dotfiles");
+    let home = root.join("home");
+
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(stow_dir.join("nvim/.config/nvim")).unwrap();
+    fs::create_dir_all(stow_dir.join("fish/.config/fish")).unwrap();
+    fs::write(stow_dir.join("nvim/.config/nvim/init.lua"), "").unwrap();
+    fs::write(stow_dir.join("nvim/README.md"), "").unwrap();
+    fs::write(stow_dir.join("fish/.config/fish/config.fish"), "").unwrap();
+    fs::create_dir_all(&home).unwrap();
+
+    let ignores = IgnoreRules::load(&stow_dir);
+    let actions = plan_package(&stow_dir, OsStr::new("nvim"), &home, &ignores, false).unwrap();
+    assert_eq!(
+        actions,
+        vec![LinkAction::CreateLink {
+            target: home.join(".config"),
+            source: stow_dir.join("nvim/.config"),
+        }]
+    );
+    actions
+        .iter()
+        .for_each(|action| apply_action(action).unwrap());
+    assert_eq!(
+        fs::read_link(home.join(".config")).unwrap(),
+        PathBuf::from("../dotfiles/nvim/.config")
+    );
+
+    let actions = plan_package(&stow_dir, OsStr::new("fish"), &home, &ignores, false).unwrap();
+    assert_eq!(
+        actions,
+        vec![
+            LinkAction::RemoveLink {
+                target: home.join(".config"),
+            },
+            LinkAction::CreateDir {
+                target: home.join(".config"),
+            },
+            LinkAction::CreateLink {
+                target: home.join(".config/nvim"),
+                source: stow_dir.join("nvim/.config/nvim"),
+            },
+            LinkAction::CreateLink {
+                target: home.join(".config/fish"),
+                source: stow_dir.join("fish/.config/fish"),
+            },
+        ]
+    );
+
+    fs::create_dir_all(stow_dir.join("zsh")).unwrap();
+    fs::write(stow_dir.join("zsh/.zshrc"), "").unwrap();
+    fs::write(home.join(".zshrc"), "").unwrap();
+
+    let actions = plan_package(&stow_dir, OsStr::new("zsh"), &home, &ignores, true).unwrap();
+    assert_eq!(
+        actions,
+        vec![
+            LinkAction::Backup {
+                target: home.join(".zshrc"),
+            },
+            LinkAction::CreateLink {
+                target: home.join(".zshrc"),
+                source: stow_dir.join("zsh/.zshrc"),
+            },
+        ]
+    );
+
+    let _ = fs::remove_dir_all(&root);
+}
@@ -1,45 +1,79 @@
 use std::{
     fs::{self, DirEntry},
-    io::Write,
-    path::PathBuf,
-    process::{Command, Output, Stdio},
+    io,
+    path::Path,
+    process::{Command, Stdio},
 };
 
-use thiserror::Error;
-
 use crate::{
+    backups::{BackupSession, ConflictPolicy},
+    checkout::checkout_dotfiles,
     config::{Config, get_home_path, load_config},
+    copies::{CopyAction, RenderContext, create_dir, install_file, is_rendered, plan_copy},
     errors::{dotfiles_clone_error, dotfiles_dir_read_error, missing_prerequirements},
+    etc_files::restore_etc_files,
+    hook::ProxiedTransaction,
+    ignores::IgnoreRules,
     manifest::{
         Manifest, Package,
         PackageManager::{self, Pacman, Paru, Yay},
         load_manifest,
     },
+    output::{child_stdout, print_detail, prompt_confirm},
+    pacman_db::{PACMAN_DB_PATH, SyncDatabases},
+    privileges::{Escalation, escalation_tool, package_manager_command},
+    repositories::prepare_repositories,
+    services::restore_services,
+    state::{DeployedKind, DotfilesState, owning_package},
     successes::{
-        dotfiles_copied_successfully, dry_run_dotfiles_clone, package_sync_success,
-        package_update_success, pacman_dry_run_header, stow_success,
+        backups_saved, dotfile_backed_up, dotfile_file_copied, dotfile_linked, dotfile_skipped,
+        dotfiles_copied_successfully, dotfiles_linked_successfully, package_sync_success,
+        package_update_success, pacman_dry_run_header, repositories_refreshed,
     },
+    symlinks::{LinkAction, apply_action, plan_package},
     warnings::{
-        dotfiles_copy_failed, warn_dotfiles_symlink_failed, warn_dotfiles_symlink_non_zero,
-        warn_dotfiles_symlink_signal_exit, warn_failed_installs,
+        dotfiles_copy_failed, warn_dotfile_conflict, warn_dotfiles_package_not_found,
+        warn_dotfiles_state_not_saved, warn_dotfiles_symlink_failed, warn_failed_installs,
     },
 };
 
-#[derive(Debug, Error)]
-pub enum RestoreError {
-    #[error("Failed to clone dotfiles repository")]
-    DotfileClone,
+/// Which dotfile packages a sync applies.
+pub enum DotfilesFilter {
+    /// The packages selected by the config for this host
+    Selected,
+    Only(Vec<String>),
+    Skip,
 }
 
-pub fn sync(dry_run: bool, verbose: bool) {
+impl DotfilesFilter {
+    /// Whether the run also syncs repositories, packages, `/etc` files and
+    /// services, `--dotfiles-only` is a partial run of the dotfiles alone.
+    fn syncs_system(&self) -> bool {
+        !matches!(self, DotfilesFilter::Only(_))
+    }
+}
+
+pub fn sync(dry_run: bool, verbose: bool, force: bool, dotfiles: DotfilesFilter) {
     let config = load_config();
 
     check_prereqs(&config);
 
-    let manifest = load_manifest();
+    if dotfiles.syncs_system() {
+        let manifest = load_manifest();
+
+        let refresh = prepare_repositories(&config, &manifest, dry_run);
+        restore_packages(&config, &manifest, refresh, dry_run, verbose);
+        restore_etc_files(&config, dry_run);
+        restore_services(&config, &manifest, dry_run);
+    }
+
+    let packages = match dotfiles {
+        DotfilesFilter::Selected => config.selected_dotfiles().map(<[String]>::to_vec),
+        DotfilesFilter::Only(packages) => Some(packages),
+        DotfilesFilter::Skip => return,
+    };
 
-    restore_packages(&config, &manifest, dry_run, verbose);
-    restore_dotfiles(&config, dry_run, verbose);
+    restore_dotfiles(&config, packages, dry_run, verbose, force);
 }
 
 pub fn check_prereqs(config: &Config) {
@@ -47,10 +81,10 @@ pub fn check_prereqs(config: &Config) {
     check_if_available(&config.package_manager.to_string(), &mut missing);
     check_if_available("git", &mut missing);
 
-    if config.dotfiles_symlink {
-        check_if_available("stow", &mut missing);
-    } else {
-        check_if_available("rsync", &mut missing);
+    if let Some(tool) = escalation_tool(&config.package_manager, &config.escalation)
+        && !check_binary_availability(&tool)
+    {
+        missing.push(tool);
     }
 
     if !missing.is_empty() {
@@ -61,46 +95,110 @@ pub fn check_prereqs(config: &Config) {
 pub fn check_if_available(cli_tool: &str, missing: &mut Vec<String>) {
     let mut command = Command::new(cli_tool);
     let Ok(output) = command.output() else {
-        println!("couldn't run command");
+        print_detail("couldn't run command");
         missing.push(cli_tool.to_string());
         return;
     };
 
     let Some(code) = output.status.code() else {
-        println!("couldn't get command code");
+        print_detail("couldn't get command code");
         missing.push(cli_tool.to_string());
         return;
     };
 
     if code == 127 {
-        println!("command code 127");
+        print_detail("command code 127");
         missing.push(cli_tool.to_string());
     }
 }
 
-fn restore_dotfiles(config: &Config, dry_run: bool, verbose: bool) {
-    match clone_dotfiles(&config.dotfiles_repo, dry_run, verbose) {
-        Ok(_) => install_dotfiles(config.dotfiles_symlink, verbose, dry_run),
-        Err(error) => dotfiles_clone_error(error, verbose),
+fn restore_dotfiles(
+    config: &Config,
+    packages: Option<Vec<String>>,
+    dry_run: bool,
+    verbose: bool,
+    force: bool,
+) {
+    match checkout_dotfiles(config, force, dry_run, verbose) {
+        Ok(_) => install_dotfiles(config, packages, verbose, dry_run),
+        Err(error) => dotfiles_clone_error(error),
     }
 }
 
-fn install_dotfiles(symlink: bool, verbose: bool, dry_run: bool) {
-    let home_path = get_home_path();
-    let dotfiles_path = home_path.join("dotfiles");
+/// What a sync of the dotfiles carries from one package to the next.
+struct Deployment {
+    backups: BackupSession,
+    state: DotfilesState,
+    render: RenderContext,
+    ignores: IgnoreRules,
+}
+
+/// Applies every package in the checkout, or only `packages` when given.
+fn install_dotfiles(config: &Config, packages: Option<Vec<String>>, verbose: bool, dry_run: bool) {
+    let dotfiles_path = config.dotfiles_path();
 
     let dotfiles_dir = match fs::read_dir(&dotfiles_path) {
         Ok(dir) => dir,
         Err(error) => dotfiles_dir_read_error(dotfiles_path, error, verbose),
     };
 
-    dotfiles_dir
+    let mut deployment = Deployment {
+        backups: BackupSession::new(),
+        state: DotfilesState::load(),
+        render: RenderContext::new(config),
+        ignores: IgnoreRules::load(&dotfiles_path),
+    };
+
+    if let Some(packages) = &packages {
+        packages
+            .iter()
+            .filter(|package| !dotfiles_path.join(package).is_dir())
+            .for_each(|package| warn_dotfiles_package_not_found(package));
+    }
+
+    let selected = |entry: &DirEntry| {
+        packages.as_ref().is_none_or(|packages| {
+            packages
+                .iter()
+                .any(|package| entry.file_name() == package.as_str())
+        })
+    };
+
+    let entries: Vec<DirEntry> = dotfiles_dir
         .flatten()
-        .filter(|entry| entry.path().is_dir())
-        .for_each(|entry| match symlink {
-            true => symlink_config(entry, verbose, dry_run),
-            false => copy_config(entry, verbose, dry_run),
+        .filter(|entry| entry.path().is_dir() && selected(entry))
+        .filter(|entry| !deployment.ignores.is_ignored(&entry.path(), true))
+        .collect();
+
+    entries
+        .into_iter()
+        .for_each(|entry| match config.dotfiles_symlink {
+            true => symlink_config(config, entry, &mut deployment, verbose, dry_run),
+            false => copy_config(config, entry, &mut deployment, verbose, dry_run),
         });
+
+    let backups = &deployment.backups;
+    if !backups.is_empty() {
+        backups_saved(&backups.id, &backups.dir);
+    }
+
+    if !dry_run && let Err(error) = deployment.state.save() {
+        warn_dotfiles_state_not_saved(error);
+    }
+}
+
+fn backup_conflict(deployment: &mut Deployment, target: &Path, dry_run: bool) -> io::Result<()> {
+    if dry_run {
+        let backup = deployment.backups.backup_path(target);
+        dotfile_backed_up(target, &backup, dry_run);
+        return Ok(());
+    }
+
+    let backup = deployment.backups.backup(target)?;
+    deployment.state.record_backup(target, &backup);
+    dotfile_backed_up(target, &backup, dry_run);
+
+    Ok(())
 }
 
 fn check_binary_availability(binary_name: &str) -> bool {
@@ -118,73 +216,155 @@ fn check_binary_availability(binary_name: &str) -> bool {
     status_code == 0
 }
 
-fn symlink_config(entry: DirEntry, verbose: bool, dry_run: bool) {
+fn symlink_config(
+    config: &Config,
+    entry: DirEntry,
+    deployment: &mut Deployment,
+    verbose: bool,
+    dry_run: bool,
+) {
     if entry.file_name().into_string().expect("").starts_with(".") {
+        dotfile_skipped(entry.file_name(), "hidden directory", verbose);
         return;
     }
 
-    // TODO: Move this to an earlier spot to check all deps up front
-    if !check_binary_availability("stow") {
-        panic!("stow is required to symlink dotfiles");
-    }
+    let dotfiles_path = config.dotfiles_path();
+    let home_path = get_home_path();
 
-    let dotfiles_path = get_home_path().join("dotfiles");
+    let backup_conflicts = config.conflict_policy == ConflictPolicy::Backup;
+    let actions = match plan_package(
+        &dotfiles_path,
+        &entry.file_name(),
+        &home_path,
+        &deployment.ignores,
+        backup_conflicts,
+    ) {
+        Ok(actions) => actions,
+        Err(error) => {
+            warn_dotfiles_symlink_failed(entry.file_name(), error);
+            return;
+        }
+    };
 
-    let mut symlink_command = Command::new("stow");
-    symlink_command.current_dir(dotfiles_path);
-    symlink_command.arg("-S");
-    symlink_command.arg(entry.file_name());
+    let conflicts: Vec<&LinkAction> = actions
+        .iter()
+        .filter(|action| matches!(action, LinkAction::Conflict { .. }))
+        .collect();
 
-    if dry_run {
-        symlink_command.arg("--simulate");
-    }
+    // NOTE: like stow, refuse the whole package so it is never half-linked
+    if !conflicts.is_empty() {
+        conflicts.iter().for_each(|conflict| {
+            if let LinkAction::Conflict { target, reason } = conflict {
+                warn_dotfile_conflict(entry.file_name(), target, reason);
+            }
+        });
 
-    if verbose {
-        symlink_command.arg("--verbose");
+        return;
     }
 
-    let symlink_result = match symlink_command.output() {
-        Ok(result) => result,
-        Err(error) => {
-            warn_dotfiles_symlink_failed(entry.file_name(), error);
+    for action in &actions {
+        if let LinkAction::Backup { target } = action {
+            if let Err(error) = backup_conflict(deployment, target, dry_run) {
+                warn_dotfiles_symlink_failed(entry.file_name(), error);
+                return;
+            }
 
+            continue;
+        }
+
+        if !dry_run && let Err(error) = apply_action(action) {
+            warn_dotfiles_symlink_failed(entry.file_name(), error);
             return;
         }
-    };
 
-    if verbose
-        && let Ok(stdout) = String::from_utf8(symlink_result.stdout)
-        && !stdout.is_empty()
-    {
-        println!("stdout {stdout:?}");
+        if !dry_run {
+            record_link_action(&dotfiles_path, &entry, &mut deployment.state, action);
+        }
+
+        match action {
+            LinkAction::CreateLink { target, source } => {
+                dotfile_linked(entry.file_name(), target, source, dry_run)
+            }
+
+            LinkAction::AlreadyLinked { target, .. } => {
+                dotfile_skipped(target.clone().into_os_string(), "already linked", verbose)
+            }
+
+            LinkAction::RemoveLink { target } if verbose => {
+                print_detail(&format!("unfolding {}", target.to_string_lossy()))
+            }
+
+            _ => {}
+        }
     }
 
-    if verbose
-        && let Ok(stderr) = String::from_utf8(symlink_result.stderr)
-        && !stderr.is_empty()
-    {
-        println!("stderr {stderr:?}");
+    // NOTE: templates and secrets are never linked, they are copied rendered
+    let package_path = dotfiles_path.join(entry.file_name());
+    let render = Some(&deployment.render);
+    let ignores = Some(&deployment.ignores);
+    let policy = &config.copy_policy;
+    let rendered: Vec<CopyAction> =
+        match plan_copy(&package_path, &home_path, policy, render, ignores) {
+            Ok(actions) => actions.into_iter().filter(is_rendered_action).collect(),
+            Err(error) => {
+                dotfiles_copy_failed(entry.file_name(), home_path, error);
+                return;
+            }
+        };
+
+    copy_actions(config, &entry, rendered, deployment, verbose, dry_run);
+    dotfiles_linked_successfully(entry.file_name());
+}
+
+fn is_rendered_action(action: &CopyAction) -> bool {
+    match action {
+        CopyAction::Create { source, .. }
+        | CopyAction::Overwrite { source, .. }
+        | CopyAction::Prompt { source, .. }
+        | CopyAction::Skip { source, .. } => is_rendered(source),
+        CopyAction::CreateDir { .. } => false,
     }
+}
 
-    let status_code = match symlink_result.status.code() {
-        Some(code) => code,
-        None => {
-            warn_dotfiles_symlink_signal_exit(entry.file_name());
-            return;
+/// Tracks what linking did so `metl dotfiles remove` can undo it. Links made
+/// while unfolding another package's directory are recorded against that
+/// package, and a backup of the folded directory moves onto the new one.
+fn record_link_action(
+    dotfiles_path: &Path,
+    entry: &DirEntry,
+    state: &mut DotfilesState,
+    action: &LinkAction,
+) {
+    let package = entry.file_name().to_string_lossy().to_string();
+
+    match action {
+        LinkAction::CreateLink { target, source }
+        | LinkAction::AlreadyLinked { target, source } => {
+            let owner = owning_package(dotfiles_path, source).unwrap_or(package);
+            state.record(&owner, target, Some(source), DeployedKind::Link);
         }
-    };
 
-    if status_code != 0 {
-        warn_dotfiles_symlink_non_zero(entry.file_name(), status_code);
-        return;
-    }
+        LinkAction::RemoveLink { target } => {
+            if let Some(backup) = state.forget(target).and_then(|file| file.backup) {
+                state.record_backup(target, &backup);
+            }
+        }
+
+        LinkAction::CreateDir { target } => state.record(&package, target, None, DeployedKind::Dir),
 
-    stow_success(entry.file_name());
+        LinkAction::Conflict { .. } | LinkAction::Backup { .. } => {}
+    }
 }
 
-fn copy_config(entry: DirEntry, verbose: bool, dry_run: bool) {
+fn copy_config(
+    config: &Config,
+    entry: DirEntry,
+    deployment: &mut Deployment,
+    verbose: bool,
+    dry_run: bool,
+) {
     let home_dir = get_home_path();
-    let dotfiles_path = home_dir.join("dotfiles");
+    let dotfiles_path = config.dotfiles_path();
 
     let parent_folder = dotfiles_path.join(entry.file_name());
 
@@ -194,114 +374,155 @@ fn copy_config(entry: DirEntry, verbose: bool, dry_run: bool) {
         return;
     }
 
-    match fs::read_dir(&parent_folder) {
-        Ok(children) => {
-            children.for_each(|child_dir| {
-                if let Ok(dir_entry) = child_dir
-                    && dir_entry.file_name().to_str() != Some(".git")
-                {
-                    let source_path = dir_entry.path();
-                    let dest_path = home_dir.join(dir_entry.file_name());
-
-                    let log_files_path = format!(
-                        "{:?}/{:?}",
-                        parent_folder.file_name().unwrap(),
-                        dir_entry.file_name()
-                    )
-                    .replace("\"", "");
-
-                    match run_rsync(&source_path, &dest_path, verbose, dry_run) {
-                        Ok(output) => dotfiles_copied_successfully(
-                            log_files_path.into(),
-                            dest_path,
-                            output,
-                            verbose,
-                        ),
-
-                        Err(error) => dotfiles_copy_failed(dir_entry.file_name(), dest_path, error),
-                    }
-                }
-            });
+    let render = Some(&deployment.render);
+    let ignores = Some(&deployment.ignores);
+    let policy = &config.copy_policy;
+    let actions = match plan_copy(&parent_folder, &home_dir, policy, render, ignores) {
+        Ok(actions) => actions,
+        Err(error) => {
+            dotfiles_copy_failed(entry.file_name(), home_dir, error);
+            return;
         }
+    };
 
-        Err(error) => dotfiles_dir_read_error(parent_folder, error, verbose),
-    }
+    copy_actions(config, &entry, actions, deployment, verbose, dry_run);
+    dotfiles_copied_successfully(entry.file_name(), home_dir);
 }
 
-fn run_rsync(
-    source: &PathBuf,
-    dest: &PathBuf,
+fn copy_actions(
+    config: &Config,
+    entry: &DirEntry,
+    actions: Vec<CopyAction>,
+    deployment: &mut Deployment,
     verbose: bool,
     dry_run: bool,
-) -> Result<Output, std::io::Error> {
-    let mut command = Command::new("rsync");
-    command.current_dir(source);
-    command.arg("--ignore-existing");
-    command.arg("-a");
-    command.arg("--atimes");
+) {
+    let package = entry.file_name().to_string_lossy().to_string();
 
-    if dry_run {
-        command.arg("--dry-run");
-    }
+    for action in actions {
+        let (source, target, kind, outcome) = match action {
+            CopyAction::Skip { target, reason, .. } => {
+                dotfile_skipped(target.into_os_string(), reason, verbose);
+                continue;
+            }
 
-    if verbose {
-        command.arg("-v");
-    }
+            CopyAction::CreateDir { source, target } => {
+                let outcome = match dry_run {
+                    true => Ok("created"),
+                    false => create_dir(&source, &target).map(|_| "created"),
+                };
+                (source, target, DeployedKind::Dir, outcome)
+            }
 
-    command.arg("./");
-    command.arg(dest);
+            CopyAction::Create { source, target } => {
+                let outcome = match dry_run {
+                    true => Ok("created"),
+                    false => {
+                        install_file(&source, &target, Some(&deployment.render)).map(|_| "created")
+                    }
+                };
+                (source, target, DeployedKind::Copy, outcome)
+            }
 
-    command.output()
-}
+            CopyAction::Overwrite { source, target } => {
+                let outcome = replace_file(config, deployment, &source, &target, dry_run);
+                (source, target, DeployedKind::Copy, outcome)
+            }
 
-fn clone_dotfiles(repo: &str, dry_run: bool, verbose: bool) -> Result<(), RestoreError> {
-    let dotfiles_path = get_home_path().join("dotfiles");
+            CopyAction::Prompt { source, target } if dry_run => {
+                (source, target, DeployedKind::Copy, Ok("would prompt"))
+            }
 
-    if dry_run {
-        dry_run_dotfiles_clone(repo, dotfiles_path);
-        return Ok(());
-    }
+            CopyAction::Prompt { source, target } => {
+                if !prompt_confirm(&format!("overwrite {}?", target.to_string_lossy())) {
+                    dotfile_skipped(target.into_os_string(), "declined", verbose);
+                    continue;
+                }
 
-    let mut clone_command = Command::new("git");
-    clone_command.arg("clone").arg(repo).arg(dotfiles_path);
+                let outcome = replace_file(config, deployment, &source, &target, dry_run);
+                (source, target, DeployedKind::Copy, outcome)
+            }
+        };
 
-    let Ok(cmd_result) = clone_command.output() else {
-        return Err(RestoreError::DotfileClone);
-    };
+        match outcome {
+            Ok(copy_action) => {
+                if !dry_run {
+                    deployment
+                        .state
+                        .record(&package, &target, Some(&source), kind);
+                }
 
-    if verbose && let Ok(stdout) = String::from_utf8(cmd_result.stdout) {
-        println!("{stdout}");
+                dotfile_file_copied(entry.file_name(), &target, copy_action, dry_run)
+            }
+            Err(error) => dotfiles_copy_failed(entry.file_name(), target, error),
+        }
     }
+}
 
-    if verbose && let Ok(stderr) = String::from_utf8(cmd_result.stderr) {
-        println!("{stderr}");
+/// Overwrites `target`, first moving it aside when the conflict policy
+/// asks for backups.
+fn replace_file(
+    config: &Config,
+    deployment: &mut Deployment,
+    source: &Path,
+    target: &Path,
+    dry_run: bool,
+) -> io::Result<&'static str> {
+    if config.conflict_policy == ConflictPolicy::Backup {
+        backup_conflict(deployment, target, dry_run)?;
     }
 
-    Ok(())
+    if !dry_run {
+        install_file(source, target, Some(&deployment.render))?;
+    }
+
+    Ok("overwritten")
 }
 
-fn restore_packages(config: &Config, manifest: &Manifest, dry_run: bool, verbose: bool) {
+/// Installs the manifest packages, with its groups expanded to their members
+/// so each one is installed and reported on its own. With `refresh` the first
+/// install upgrades the whole system, so a new repository never leads to a
+/// partial upgrade.
+fn restore_packages(
+    config: &Config,
+    manifest: &Manifest,
+    refresh: bool,
+    dry_run: bool,
+    verbose: bool,
+) {
+    let sync = SyncDatabases::load(Path::new(PACMAN_DB_PATH));
+    let packages = manifest.expanded_packages(&sync.groups);
+
+    // NOTE: the hook would otherwise rewrite the manifest mid sync
+    let _transaction = ProxiedTransaction::start();
+
     match config.package_manager {
         Pacman => install_arch_packages(
             PackageManager::Pacman,
-            &manifest.packages,
+            &config.escalation,
+            &packages,
             config.locked_versions,
+            refresh,
             dry_run,
             verbose,
         ),
 
         Paru => install_arch_packages(
             PackageManager::Paru,
-            &manifest.packages,
+            &config.escalation,
+            &packages,
             config.locked_versions,
+            refresh,
             dry_run,
             verbose,
         ),
 
         Yay => install_arch_packages(
             PackageManager::Yay,
-            &manifest.packages,
+            &config.escalation,
+            &packages,
             config.locked_versions,
+            refresh,
             dry_run,
             verbose,
         ),
@@ -310,78 +531,84 @@ fn restore_packages(config: &Config, manifest: &Manifest, dry_run: bool, verbose
 
 fn install_arch_packages(
     manager: PackageManager,
+    escalation: &Escalation,
     packages: &[Package],
     locked: bool,
+    refresh: bool,
     dry_run: bool,
     verbose: bool,
 ) {
     let package_list: Vec<String> = packages
         .iter()
-        .map(|p| {
-            if locked && p.version.is_some() {
-                format!("{}={}", p.name, p.version.as_ref().unwrap())
-            } else {
-                p.name.clone()
-            }
+        .map(|p| match (locked, &p.version) {
+            (true, Some(version)) => format!("{}={}", p.name, version),
+            _ => p.name.clone(),
         })
         .collect();
 
     let mut install_errors: Vec<(&String, Option<std::io::Error>)> = vec![];
-    package_list.iter().for_each(|package| {
-        let mut command = Command::new("sudo");
-        command.arg(manager.to_string());
-        command.arg("-S");
-        command.arg("--needed");
-        command.arg("--noconfirm");
-        command.arg("--color");
-        command.arg("always");
-
-        if dry_run {
-            command.arg("-p");
-        }
+    package_list
+        .iter()
+        .enumerate()
+        .for_each(|(index, package)| {
+            let refreshing = refresh && index == 0;
+
+            let mut command = package_manager_command(&manager, escalation);
+            command.arg(match refreshing {
+                true => "-Syu",
+                false => "-S",
+            });
+            command.arg("--needed");
+            command.arg("--noconfirm");
+            command.arg("--color");
+            command.arg("always");
 
-        if verbose {
-            command.arg("--verbose");
-        }
+            if dry_run {
+                command.arg("-p");
+            }
 
-        command.arg(package);
+            if verbose {
+                command.arg("--verbose");
+            }
 
-        // NOTE: inherit so we can capture sudo password input
-        command.stdin(Stdio::inherit());
-        command.stdout(Stdio::inherit());
-        command.stderr(Stdio::inherit());
+            command.arg(package);
 
-        let command_result = match command.output() {
-            Ok(result) => result,
-            Err(error) => {
-                install_errors.push((package, Some(error)));
-                return;
-            }
-        };
+            // NOTE: inherit so we can capture the escalation password input
+            command.stdin(Stdio::inherit());
+            command.stdout(child_stdout());
+            command.stderr(Stdio::inherit());
 
-        if dry_run {
-            pacman_dry_run_header();
-        }
+            let command_result = match command.output() {
+                Ok(result) => result,
+                Err(error) => {
+                    install_errors.push((package, Some(error)));
+                    return;
+                }
+            };
 
-        if verbose {
-            let _ = std::io::stdout().write_all(&command_result.stdout);
-        }
+            if dry_run {
+                pacman_dry_run_header();
+            }
 
-        if verbose && !command_result.status.success() {
-            let stderr = String::from_utf8(command_result.stderr);
-            println!("{stderr:?}");
+            if verbose && !command_result.status.success() {
+                let stderr = String::from_utf8(command_result.stderr);
+                print_detail(&format!("{stderr:?}"));
 
-            install_errors.push((package, None));
-        }
+                install_errors.push((package, None));
+            }
 
-        if let Some(code) = command_result.status.code()
-            && code == 0
-        {
-            package_update_success(&manager, package);
-        } else {
-            install_errors.push((package, None));
-        }
-    });
+            if let Some(code) = command_result.status.code()
+                && code == 0
+            {
+                if refreshing {
+                    repositories_refreshed();
+                }
+
+                package_update_success(&manager, package);
+            } else {
+                install_errors.push((package, None));
+            }
+        });
 
     if !install_errors.is_empty() {
         warn_failed_installs(&manager, &install_errors);
@@ -391,3 +618,10 @@ fn install_arch_packages(
         package_sync_success(&manager.clone(), &package_list, &install_errors);
     }
 }
+
+#[test]
+fn test_dotfiles_only_skips_system() {
+    assert!(DotfilesFilter::Selected.syncs_system());
+    assert!(DotfilesFilter::Skip.syncs_system());
+    assert!(!DotfilesFilter::Only(vec!["nvim".to_string()]).syncs_system());
+}
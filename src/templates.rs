@@ -0,0 +1,266 @@
+use std::{collections::BTreeMap, env, path::Path};
+
+use thiserror::Error;
+
+use crate::config::{Config, get_hostname};
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TemplateError {
+    #[error("line {line}: unknown variable `{name}`")]
+    UnknownVariable { name: String, line: usize },
+
+    #[error("line {line}: `{{{{` is never closed")]
+    Unclosed { line: usize },
+
+    #[error("line {line}: invalid tag `{tag}`")]
+    InvalidTag { tag: String, line: usize },
+
+    #[error("line {line}: `{tag}` without a matching `if`")]
+    Unmatched { tag: String, line: usize },
+
+    #[error("line {line}: `if` is never closed with `end`")]
+    MissingEnd { line: usize },
+}
+
+/// Variables available to `*.tmpl` dotfiles.
+///
+/// `{{ name }}` is replaced by the value of `name`, and lines between
+/// `{{ if name == "value" }}`, `{{ else }}` and `{{ end }}` are only kept when
+/// the condition holds. A bare `{{ if name }}` checks the variable is set and
+/// not empty, `!=` is also supported. Block tags sit on their own line, which
+/// is dropped from the output.
+pub struct TemplateContext {
+    variables: BTreeMap<String, String>,
+}
+
+struct Block {
+    line: usize,
+    parent_active: bool,
+    matched: bool,
+    in_else: bool,
+}
+
+pub fn is_template(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "tmpl")
+}
+
+impl TemplateContext {
+    pub fn new(config: &Config) -> Self {
+        let mut variables = BTreeMap::from([
+            ("hostname".to_string(), get_hostname()),
+            ("user".to_string(), env::var("USER").unwrap_or_default()),
+        ]);
+
+        // NOTE: config variables win so a host can override the built ins
+        variables.extend(config.variables.clone());
+
+        TemplateContext { variables }
+    }
+
+    fn lookup(&self, name: &str) -> Option<String> {
+        match name.strip_prefix("env.") {
+            Some(name) => env::var(name).ok(),
+            None => self.variables.get(name).cloned(),
+        }
+    }
+
+    pub fn render(&self, source: &str) -> Result<String, TemplateError> {
+        let mut rendered = String::with_capacity(source.len());
+        let mut blocks: Vec<Block> = vec![];
+
+        for (index, line) in source.split_inclusive('\n').enumerate() {
+            let number = index + 1;
+            let active = blocks
+                .last()
+                .is_none_or(|block| block.parent_active && (block.matched != block.in_else));
+
+            if let Some(tag) = block_tag(line) {
+                self.apply_block(tag, number, active, &mut blocks)?;
+                continue;
+            }
+
+            if active {
+                rendered.push_str(&self.substitute(line, number)?);
+            }
+        }
+
+        match blocks.last() {
+            Some(block) => Err(TemplateError::MissingEnd { line: block.line }),
+            None => Ok(rendered),
+        }
+    }
+
+    fn apply_block(
+        &self,
+        tag: &str,
+        line: usize,
+        active: bool,
+        blocks: &mut Vec<Block>,
+    ) -> Result<(), TemplateError> {
+        if let Some(condition) = tag.strip_prefix("if ") {
+            blocks.push(Block {
+                line,
+                parent_active: active,
+                matched: self.evaluate(condition.trim(), line)?,
+                in_else: false,
+            });
+
+            return Ok(());
+        }
+
+        let unmatched = || TemplateError::Unmatched {
+            tag: tag.to_string(),
+            line,
+        };
+
+        match tag {
+            "else" => {
+                let block = blocks.last_mut().filter(|block| !block.in_else);
+                block.ok_or_else(unmatched)?.in_else = true;
+            }
+            "end" => {
+                blocks.pop().ok_or_else(unmatched)?;
+            }
+            _ => unreachable!("block_tag only returns if, else and end"),
+        }
+
+        Ok(())
+    }
+
+    fn evaluate(&self, condition: &str, line: usize) -> Result<bool, TemplateError> {
+        let invalid = || TemplateError::InvalidTag {
+            tag: format!("if {condition}"),
+            line,
+        };
+
+        for (operator, equal) in [("==", true), ("!=", false)] {
+            if let Some((name, expected)) = condition.split_once(operator) {
+                let name = variable_name(name).ok_or_else(invalid)?;
+                let expected = expected
+                    .trim()
+                    .strip_prefix('"')
+                    .and_then(|expected| expected.strip_suffix('"'))
+                    .ok_or_else(invalid)?;
+
+                let value = self.lookup(name).unwrap_or_default();
+                return Ok((value == expected) == equal);
+            }
+        }
+
+        let name = variable_name(condition).ok_or_else(invalid)?;
+        Ok(self.lookup(name).is_some_and(|value| !value.is_empty()))
+    }
+
+    fn substitute(&self, line: &str, number: usize) -> Result<String, TemplateError> {
+        let mut substituted = String::with_capacity(line.len());
+        let mut rest = line;
+
+        while let Some(start) = rest.find("{{") {
+            substituted.push_str(&rest[..start]);
+
+            let Some(end) = rest[start..].find("}}") else {
+                return Err(TemplateError::Unclosed { line: number });
+            };
+
+            let tag = &rest[start + 2..start + end];
+            let Some(name) = variable_name(tag) else {
+                return Err(TemplateError::InvalidTag {
+                    tag: tag.trim().to_string(),
+                    line: number,
+                });
+            };
+
+            match self.lookup(name) {
+                Some(value) => substituted.push_str(&value),
+                None => {
+                    return Err(TemplateError::UnknownVariable {
+                        name: name.to_string(),
+                        line: number,
+                    });
+                }
+            }
+
+            rest = &rest[start + end + 2..];
+        }
+
+        substituted.push_str(rest);
+        Ok(substituted)
+    }
+}
+
+/// The tag of a line holding nothing but `{{ if .. }}`, `{{ else }}` or
+/// `{{ end }}`.
+fn block_tag(line: &str) -> Option<&str> {
+    let tag = line.trim().strip_prefix("{{")?.strip_suffix("}}")?.trim();
+
+    match tag {
+        "else" | "end" => Some(tag),
+        tag if tag.starts_with("if ") => Some(tag),
+        _ => None,
+    }
+}
+
+fn variable_name(tag: &str) -> Option<&str> {
+    let name = tag.trim();
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+
+    valid.then_some(name)
+}
+
+#[test]
+fn test_render_template() {
+    let context = TemplateContext {
+        variables: BTreeMap::from([
+            ("hostname".to_string(), "laptop".to_string()),
+            ("email".to_string(), "me@example.com".to_string()),
+            ("font_size".to_string(), String::new()),
+        ]),
+    };
+
+    let template = r#"[user]
+    email = {{ email }}
+{{ if hostname == "laptop" }}
+monitor = eDP-1
+  {{ if font_size }}
+font = {{ font_size }}
+  {{ else }}
+font = 11
+  {{ end }}
+{{ else }}
+monitor = DP-1
+{{ end }}
+{{ if hostname != "laptop" }}
+desktop = true
+{{ end }}
+"#;
+
+    assert_eq!(
+        context.render(template),
+        Ok("[user]\n    email = me@example.com\nmonitor = eDP-1\nfont = 11\n".to_string())
+    );
+
+    assert_eq!(
+        context.render("{{ missing }}\n"),
+        Err(TemplateError::UnknownVariable {
+            name: "missing".to_string(),
+            line: 1,
+        })
+    );
+
+    assert_eq!(
+        context.render("{{ if email }}\n"),
+        Err(TemplateError::MissingEnd { line: 1 })
+    );
+
+    assert_eq!(
+        context.render("ok\n{{ end }}\n"),
+        Err(TemplateError::Unmatched {
+            tag: "end".to_string(),
+            line: 2,
+        })
+    );
+}
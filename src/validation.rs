@@ -0,0 +1,136 @@
+use std::{fmt::Display, ops::Range};
+
+use serde::de::DeserializeOwned;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub source_line: Option<String>,
+    pub suggestion: Option<String>,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, "line {line}, column {column}: ")?;
+        }
+
+        write!(f, "{}", self.message)?;
+
+        if let Some(source_line) = &self.source_line {
+            write!(f, "\n\t| {source_line}")?;
+        }
+
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\n\tdid you mean {suggestion}?")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Deserializes `source` and turns any error into a `Diagnostic` that points
+/// at the offending line and suggests the closest valid key or value.
+pub fn parse_toml<T: DeserializeOwned>(source: &str) -> Result<T, Diagnostic> {
+    toml::from_str::<T>(source).map_err(|error| diagnose(source, &error))
+}
+
+pub fn diagnose_syntax(source: &str, error: &toml_edit::TomlError) -> Diagnostic {
+    diagnose_span(source, error.message(), error.span())
+}
+
+fn diagnose(source: &str, error: &toml::de::Error) -> Diagnostic {
+    diagnose_span(source, error.message(), error.span())
+}
+
+fn diagnose_span(source: &str, message: &str, span: Option<Range<usize>>) -> Diagnostic {
+    let message = message.trim().to_string();
+
+    let (line, column, source_line) = match span {
+        Some(span) => {
+            let (line, column) = line_column(source, span.start);
+            let source_line = source
+                .lines()
+                .nth(line - 1)
+                .map(|l| l.trim_end().to_string());
+
+            (Some(line), Some(column), source_line)
+        }
+
+        None => (None, None, None),
+    };
+
+    Diagnostic {
+        suggestion: suggest(&message),
+        message,
+        line,
+        column,
+        source_line,
+    }
+}
+
+fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1;
+
+    (line, column)
+}
+
+/// serde reports unknown keys and enum values as
+/// "unknown variant `x`, expected one of `a`, `b`", so the offending token
+/// and the valid candidates are the backticked words in order.
+fn suggest(message: &str) -> Option<String> {
+    if !message.starts_with("unknown variant") && !message.starts_with("unknown field") {
+        return None;
+    }
+
+    let mut quoted = message.split('`').skip(1).step_by(2);
+    let unknown = quoted.next()?;
+
+    quoted
+        .map(|candidate| (edit_distance(unknown, candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= candidate.len().max(unknown.len()) / 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[test]
+fn test_diagnostic_suggests_closest_variant() {
+    use crate::config::Config;
+
+    let toml = r#"package_manager = "parru"
+locked_versions = true
+dotfiles_repo = "repo_url"
+dotfiles_symlink = true
+manifest_repo = "repo_url"
+"#;
+
+    let Err(diagnostic) = parse_toml::<Config>(toml) else {
+        panic!("Expected parru to be rejected");
+    };
+
+    assert_eq!(diagnostic.line, Some(1));
+    assert_eq!(diagnostic.column, Some(19));
+    assert_eq!(diagnostic.suggestion, Some("paru".to_string()));
+}
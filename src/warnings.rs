@@ -1,52 +1,61 @@
-use std::{ffi::OsString, path::PathBuf, sync::LazyLock};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
 
 use colored::{ColoredString, Colorize};
 
-use crate::manifest::PackageManager;
+use crate::{
+    manifest::PackageManager,
+    output::{Event, emit, is_json},
+};
 
 static WARNING: LazyLock<ColoredString> = LazyLock::new(|| "[WARNING]".yellow().bold());
 
-pub fn warn_package_output(package_output: &str) {
-    println!(
-        "{} {} {}",
-        &*WARNING,
-        "Invalid package format:".white().dimmed(),
-        package_output.magenta().bold()
-    );
-}
-
 pub fn warn_dotfiles_symlink_failed(name: OsString, error: std::io::Error) {
+    if is_json() {
+        return emit(Event::DotfileFailed {
+            name: name.to_string_lossy().to_string(),
+            error: error.to_string(),
+        });
+    }
+
     println!(
         "{} {} {}\n{}",
         &*WARNING,
         name.to_string_lossy().white().bold(),
-        "could not be stowed".white().dimmed(),
+        "could not be symlinked".white().dimmed(),
         error.to_string().cyan().bold(),
     );
 }
 
-pub fn warn_dotfiles_symlink_signal_exit(name: OsString) {
-    println!(
-        "{} {} {}",
-        &*WARNING,
-        name.to_string_lossy().white().bold(),
-        "could not be stowed, exited because of signal"
-            .white()
-            .dimmed(),
-    );
-}
+pub fn warn_dotfile_conflict(name: OsString, target: &Path, reason: &str) {
+    if is_json() {
+        return emit(Event::DotfileFailed {
+            name: name.to_string_lossy().to_string(),
+            error: format!("conflict at {}: {reason}", target.to_string_lossy()),
+        });
+    }
 
-pub fn warn_dotfiles_symlink_non_zero(name: OsString, code: i32) {
     println!(
-        "{} {} {} {}",
+        "{} {} {} {}\n{}",
         &*WARNING,
         name.to_string_lossy().white().bold(),
-        "could not be stowed, exit code:".white().dimmed(),
-        code.to_string().white().bold()
+        "not linked, conflict at".white().dimmed(),
+        target.to_string_lossy().white().bold(),
+        reason.cyan().bold(),
     );
 }
 
 pub fn dotfiles_copy_failed(name: OsString, to: PathBuf, error: std::io::Error) {
+    if is_json() {
+        return emit(Event::DotfileFailed {
+            name: name.to_string_lossy().to_string(),
+            error: format!("could not be copied to {}: {error}", to.to_string_lossy()),
+        });
+    }
+
     println!(
         "{} {} {} {}\n{}",
         &*WARNING,
@@ -61,6 +70,17 @@ pub fn warn_failed_installs(
     manager: &PackageManager,
     install_errors: &[(&String, Option<std::io::Error>)],
 ) {
+    if is_json() {
+        return install_errors.iter().for_each(|(package, error)| {
+            emit(Event::PackageFailed {
+                manager: manager.to_string(),
+                package: package.to_string(),
+                code: None,
+                error: error.as_ref().map(|err| err.to_string()),
+            })
+        });
+    }
+
     install_errors
         .iter()
         .for_each(|(package, error)| match error {
@@ -87,13 +107,25 @@ pub fn warn_failed_installs(
         });
 }
 
-pub fn warn_git_add_metl_manifest_failed(error: Option<std::io::Error>) {
+pub fn warn_git_add_metl_manifest_failed(repo: &str, error: Option<std::io::Error>) {
+    if is_json() {
+        return emit(Event::CommitFailed {
+            repo: repo.to_string(),
+            stage: "add".to_string(),
+            code: None,
+            error: error.map(|err| err.to_string()),
+        });
+    }
+
     match error {
         Some(err) => {
             println!(
                 "{} {}\n{}",
                 &*WARNING,
-                "failed to git add metl manifest files".white().dimmed(),
+                format!("failed to git add {repo} files")
+                    .as_str()
+                    .white()
+                    .dimmed(),
                 err.to_string().cyan().bold(),
             );
         }
@@ -101,30 +133,55 @@ pub fn warn_git_add_metl_manifest_failed(error: Option<std::io::Error>) {
             println!(
                 "{} {}",
                 &*WARNING,
-                "failed to git add metl manifest files".white().dimmed(),
+                format!("failed to git add {repo} files")
+                    .as_str()
+                    .white()
+                    .dimmed(),
             );
         }
     }
 }
 
-pub fn warn_git_add_metl_manifest_code(code: i32) {
+pub fn warn_git_add_metl_manifest_code(repo: &str, code: i32) {
+    if is_json() {
+        return emit(Event::CommitFailed {
+            repo: repo.to_string(),
+            stage: "add".to_string(),
+            code: Some(code),
+            error: None,
+        });
+    }
+
     println!(
         "{} {} {}",
         &*WARNING,
-        "failed to git add metl manifest files, code:"
+        format!("failed to git add {repo} files, code:")
+            .as_str()
             .white()
             .dimmed(),
         code.to_string().cyan().bold(),
     );
 }
 
-pub fn warn_metl_manifest_commit_failed(error: Option<std::io::Error>) {
+pub fn warn_metl_manifest_commit_failed(repo: &str, error: Option<std::io::Error>) {
+    if is_json() {
+        return emit(Event::CommitFailed {
+            repo: repo.to_string(),
+            stage: "commit".to_string(),
+            code: None,
+            error: error.map(|err| err.to_string()),
+        });
+    }
+
     match error {
         Some(err) => {
             println!(
                 "{} {}\n{}",
                 &*WARNING,
-                "failed to git commit metl manifest files".white().dimmed(),
+                format!("failed to git commit {repo} files")
+                    .as_str()
+                    .white()
+                    .dimmed(),
                 err.to_string().cyan().bold(),
             );
         }
@@ -132,30 +189,57 @@ pub fn warn_metl_manifest_commit_failed(error: Option<std::io::Error>) {
             println!(
                 "{} {}",
                 &*WARNING,
-                "failed to git commit metl manifest files".white().dimmed(),
+                format!("failed to git commit {repo} files")
+                    .as_str()
+                    .white()
+                    .dimmed(),
             );
         }
     }
 }
 
-pub fn warn_metl_manifest_commit_code(code: i32) {
+pub fn warn_metl_manifest_commit_code(repo: &str, code: i32) {
+    if is_json() {
+        return emit(Event::CommitFailed {
+            repo: repo.to_string(),
+            stage: "commit".to_string(),
+            code: Some(code),
+            error: None,
+        });
+    }
+
     println!(
         "{} {} {}",
         &*WARNING,
-        "failed to git commit metl manifest files, code:"
+        format!("failed to git commit {repo} files, code:")
+            .as_str()
             .white()
             .dimmed(),
         code.to_string().cyan().bold(),
     );
 }
 
-pub fn warn_git_push_metl_manifest_failed(error: Option<std::io::Error>, code: Option<i32>) {
+pub fn warn_git_push_metl_manifest_failed(
+    repo: &str,
+    error: Option<std::io::Error>,
+    code: Option<i32>,
+) {
+    if is_json() {
+        return emit(Event::CommitFailed {
+            repo: repo.to_string(),
+            stage: "push".to_string(),
+            code,
+            error: error.map(|err| err.to_string()),
+        });
+    }
+
     match (error, code) {
         (None, None) => {
             println!(
                 "{} {}",
                 &*WARNING,
-                "failed to git push metl manifest files, code:"
+                format!("failed to git push {repo} files, code:")
+                    .as_str()
                     .white()
                     .dimmed(),
             );
@@ -164,7 +248,8 @@ pub fn warn_git_push_metl_manifest_failed(error: Option<std::io::Error>, code: O
             println!(
                 "{} {} {}",
                 &*WARNING,
-                "failed to git push metl manifest files, code:"
+                format!("failed to git push {repo} files, code:")
+                    .as_str()
                     .white()
                     .dimmed(),
                 code.to_string().cyan(),
@@ -174,7 +259,8 @@ pub fn warn_git_push_metl_manifest_failed(error: Option<std::io::Error>, code: O
             println!(
                 "{} {} {}",
                 &*WARNING,
-                "failed to git push metl manifest files, error:"
+                format!("failed to git push {repo} files, error:")
+                    .as_str()
                     .white()
                     .dimmed(),
                 error.to_string().cyan(),
@@ -184,7 +270,8 @@ pub fn warn_git_push_metl_manifest_failed(error: Option<std::io::Error>, code: O
             println!(
                 "{} {} {}\n{}",
                 &*WARNING,
-                "failed to git push metl manifest files, code:"
+                format!("failed to git push {repo} files, code:")
+                    .as_str()
                     .white()
                     .dimmed(),
                 code.to_string().cyan(),
@@ -193,3 +280,270 @@ pub fn warn_git_push_metl_manifest_failed(error: Option<std::io::Error>, code: O
         }
     }
 }
+
+pub fn warn_config_migrated(from: i64, to: i64) {
+    if is_json() {
+        return emit(Event::Warning {
+            message: format!("metl config migrated from version {from} to {to}"),
+        });
+    }
+
+    println!(
+        "{} {} {} {} {}",
+        &*WARNING,
+        "metl config migrated from version".white().dimmed(),
+        from.to_string().white().bold(),
+        "to".white().dimmed(),
+        to.to_string().white().bold(),
+    );
+}
+
+pub fn warn_dotfile_restore_failed(original: &Path, reason: &str) {
+    if is_json() {
+        return emit(Event::DotfileFailed {
+            name: original.to_string_lossy().to_string(),
+            error: format!("could not be restored: {reason}"),
+        });
+    }
+
+    println!(
+        "{} {} {}\n{}",
+        &*WARNING,
+        original.to_string_lossy().white().bold(),
+        "could not be restored".white().dimmed(),
+        reason.cyan().bold(),
+    );
+}
+
+pub fn warn_dotfile_adopt_failed(original: &Path, reason: &str) {
+    if is_json() {
+        return emit(Event::DotfileFailed {
+            name: original.to_string_lossy().to_string(),
+            error: format!("could not be adopted: {reason}"),
+        });
+    }
+
+    println!(
+        "{} {} {}\n{}",
+        &*WARNING,
+        original.to_string_lossy().white().bold(),
+        "could not be adopted".white().dimmed(),
+        reason.cyan().bold(),
+    );
+}
+
+pub fn warn_dotfile_remove_failed(target: &Path, reason: &str) {
+    if is_json() {
+        return emit(Event::DotfileFailed {
+            name: target.to_string_lossy().to_string(),
+            error: format!("could not be removed: {reason}"),
+        });
+    }
+
+    println!(
+        "{} {} {}\n{}",
+        &*WARNING,
+        target.to_string_lossy().white().bold(),
+        "could not be removed".white().dimmed(),
+        reason.cyan().bold(),
+    );
+}
+
+pub fn warn_secret_copy_stale(target: &Path, reason: &str) {
+    if is_json() {
+        return emit(Event::DotfileFailed {
+            name: target.to_string_lossy().to_string(),
+            error: format!("still holds the old secret, {reason}, run metl sync to update it"),
+        });
+    }
+
+    println!(
+        "{} {} {}\n{}",
+        &*WARNING,
+        target.to_string_lossy().white().bold(),
+        "still holds the old secret, run metl sync to update it"
+            .white()
+            .dimmed(),
+        reason.cyan().bold(),
+    );
+}
+
+pub fn warn_dotfiles_state_not_saved(error: std::io::Error) {
+    if is_json() {
+        return emit(Event::Warning {
+            message: format!("Could not save dotfiles state: {error}"),
+        });
+    }
+
+    println!(
+        "{} {}\n{}",
+        &*WARNING,
+        "Could not save dotfiles state, metl dotfiles remove may miss files"
+            .white()
+            .dimmed(),
+        error.to_string().cyan().bold(),
+    );
+}
+
+pub fn warn_dotfiles_package_not_found(package: &str) {
+    if is_json() {
+        return emit(Event::Warning {
+            message: format!("Dotfiles package not found in the checkout: {package}"),
+        });
+    }
+
+    println!(
+        "{} {} {}",
+        &*WARNING,
+        "Dotfiles package not found in the checkout:"
+            .white()
+            .dimmed(),
+        package.white().bold(),
+    );
+}
+
+pub fn warn_metlignore_invalid(path: &Path, error: ignore::Error) {
+    if is_json() {
+        return emit(Event::Warning {
+            message: format!("Invalid pattern in {}: {error}", path.to_string_lossy()),
+        });
+    }
+
+    println!(
+        "{} {} {}\n{}",
+        &*WARNING,
+        "Invalid pattern in".white().dimmed(),
+        path.to_string_lossy().white().bold(),
+        error.to_string().cyan().bold(),
+    );
+}
+
+pub fn warn_manifest_not_written(error: std::io::Error) {
+    if is_json() {
+        return emit(Event::Warning {
+            message: format!("Could not write the manifest: {error}"),
+        });
+    }
+
+    println!(
+        "{} {}\n{}",
+        &*WARNING,
+        "Could not write the manifest".white().dimmed(),
+        error.to_string().cyan().bold(),
+    );
+}
+
+pub fn warn_dotfile_diff_failed(target: &Path, error: std::io::Error) {
+    if is_json() {
+        return emit(Event::DotfileFailed {
+            name: target.to_string_lossy().to_string(),
+            error: format!("could not be diffed: {error}"),
+        });
+    }
+
+    println!(
+        "{} {} {}\n{}",
+        &*WARNING,
+        target.to_string_lossy().white().bold(),
+        "could not be diffed".white().dimmed(),
+        error.to_string().cyan().bold(),
+    );
+}
+
+pub fn warn_etc_file_failed(path: &Path, reason: &str) {
+    if is_json() {
+        return emit(Event::Warning {
+            message: format!("{}: {reason}", path.to_string_lossy()),
+        });
+    }
+
+    println!(
+        "{} {} {}",
+        &*WARNING,
+        path.to_string_lossy().white().bold(),
+        reason.cyan().bold(),
+    );
+}
+
+pub fn warn_repository_failed(repository: &str, reason: &str) {
+    if is_json() {
+        return emit(Event::Warning {
+            message: format!("Repository {repository}: {reason}"),
+        });
+    }
+
+    println!(
+        "{} {} {} {}",
+        &*WARNING,
+        "repository".white().dimmed(),
+        repository.white().bold(),
+        reason.cyan().bold(),
+    );
+}
+
+pub fn warn_service_failed(service: &str, reason: &str) {
+    if is_json() {
+        return emit(Event::Warning {
+            message: format!("Could not enable {service}: {reason}"),
+        });
+    }
+
+    println!(
+        "{} {} {}\n{}",
+        &*WARNING,
+        "could not enable".white().dimmed(),
+        service.white().bold(),
+        reason.cyan().bold(),
+    );
+}
+
+pub fn warn_services_unavailable(scope: &str, reason: &str) {
+    if is_json() {
+        return emit(Event::Warning {
+            message: format!("Could not list {scope} services: {reason}"),
+        });
+    }
+
+    println!(
+        "{} {} {}\n{}",
+        &*WARNING,
+        "could not list".white().dimmed(),
+        format!("{scope} services").white().bold(),
+        reason.cyan().bold(),
+    );
+}
+
+pub fn warn_unknown_group(group: &str) {
+    if is_json() {
+        return emit(Event::Warning {
+            message: format!("Group {group} is not in any sync database, skipping it"),
+        });
+    }
+
+    println!(
+        "{} {} {} {}",
+        &*WARNING,
+        "group".white().dimmed(),
+        group.white().bold(),
+        "is not in any sync database, skipping it".white().dimmed(),
+    );
+}
+
+pub fn warn_sync_db_unreadable(path: &Path, error: std::io::Error) {
+    if is_json() {
+        return emit(Event::Warning {
+            message: format!(
+                "Could not read sync database {}: {error}",
+                path.to_string_lossy()
+            ),
+        });
+    }
+
+    println!(
+        "{} {} {}\n{}",
+        &*WARNING,
+        "could not read sync database".white().dimmed(),
+        path.to_string_lossy().white().bold(),
+        error.to_string().cyan().bold(),
+    );
+}